@@ -1,47 +1,134 @@
-use std::io::prelude::*;
-use std::fs::File;
 use colored::Colorize;
-use error::Error;
+use error::{Error, QueryError};
 use motions::Motion;
-use driver::Driver;
+use driver::{Driver, ExecuteOptions, Record};
+use source::{Source, SourceError};
+use variables::Variables;
+use aliases::Aliases;
 
 #[derive(Eq, PartialEq, Debug)]
 struct State {
   applied: Vec<Motion>,
   unapplied: Vec<Motion>,
+  /// Motions which have not been applied but come before motions which have,
+  /// usually because they arrived in a merge. These are in order.
+  late: Vec<Motion>,
+}
+
+/// A change to the records of a database which brings them up to date with
+/// motions that were renamed or squashed after they were applied.
+#[derive(Eq, PartialEq, Debug)]
+enum Change {
+  /// Gives a record the new name of its motion.
+  Rename { old_name: String, new_name: String, message: String },
+  /// Replaces the records of motions which were squashed with a record of the
+  /// motion they were squashed into.
+  Squash { names: Vec<String>, record: Record, message: Option<String> },
 }
 
 pub struct Accelerator {
   driver: Box<Driver>,
+  /// Changes to our records which have been made to our state but not yet to
+  /// the database.
+  changes: Vec<Change>,
+  /// Motions which are executed again whenever they change, in the order they
+  /// should be executed.
+  repeatables: Vec<Motion>,
   state: State,
+  /// Values for the placeholders in our motions.
+  variables: Variables,
+  /// Whether late motions may be applied after the motions they come before.
+  allow_out_of_order: bool,
 }
 
 impl Accelerator {
-  pub fn new(driver: Box<Driver>, motions: Vec<Motion>) -> Result<Self, Error> {
-    let records = try!(driver.get_records());
-    let state = try!(diff_motions(records, motions));
+  /// Compares our motions with the records of those which were applied. Any
+  /// motion we don’t `keep`, like one without the tags we asked for, is left
+  /// out along with its record, as if it didn’t exist.
+  pub fn new<F>(driver: Box<Driver>, motions: Vec<Motion>, variables: Variables, aliases: &Aliases, keep: F) -> Result<Self, Error> where F: Fn(&Motion) -> bool {
+    // Give the records of renamed and squashed motions their new names first,
+    // so they are found like any other record. Nothing is written until we are
+    // asked to reconcile our records.
+    let mut records = try!(driver.get_records());
+    let mut repeatable_records = try!(driver.get_repeatable_records());
+    // Motions recorded before they had a namespace would be applied again.
+    if let Some(name) = try!(find_adoptable(&*driver, &motions, aliases)).first() {
+      let motion = motions.iter().find(|motion| motion.name == aliases.resolve(name)).unwrap();
+      return Err(error!(
+        "The '{}' motion was recorded before motions had a namespace, so it would be applied again. Move the records of these motions into the namespace first with `accelerate adopt-records`.",
+        motion
+      ));
+    }
+    let mut changes = rename_records(&mut records, &mut repeatable_records, &motions, aliases);
+    changes.extend(try!(squash_records(&mut records, &motions, aliases)));
+    // Leaving out a motion which was applied must not make the motions after
+    // it look out of order, so its record goes too.
+    let (motions, left_out): (Vec<Motion>, Vec<Motion>) = motions.into_iter().partition(|motion| keep(motion));
+    records.retain(|record| !left_out.iter().any(|motion| motion.name == record.name));
+    // Repeatable motions are not applied in order like the rest, so they
+    // don’t take part in our state.
+    let (repeatables, motions): (Vec<Motion>, Vec<Motion>) = motions.into_iter().partition(|motion| motion.repeatable);
+    let state = try!(diff_motions(records.iter().map(|record| record.name.clone()).collect(), motions));
+    // Make sure nobody has edited a motion after it was applied, otherwise this
+    // database and others the motion was applied to may have diverged.
+    for motion in state.applied.iter() {
+      if let Some(record) = records.iter().find(|record| record.name == motion.name) {
+        if try!(has_changed(motion, record)) {
+          return Err(error!(
+            "The '{}' motion has changed since it was applied. If the change is intended, accept it with `accelerate accept-checksum {}`.",
+            motion,
+            motion.name
+          ));
+        }
+      }
+    }
     Ok(Accelerator {
       driver: driver,
+      changes: changes,
+      repeatables: repeatables,
       state: state,
+      variables: variables,
+      allow_out_of_order: false,
     })
   }
 
+  /// Writes the changes to our records from renamed and squashed motions to
+  /// the database, all in one transaction so a failure leaves none of them.
+  pub fn reconcile_records(&mut self) -> Result<(), Error> {
+    try!(write_changes_in_transaction(&mut *self.driver, &self.changes));
+    self.changes.clear();
+    Ok(())
+  }
+
+  /// Lets `add` apply late motions, before any other unapplied motions.
+  pub fn set_allow_out_of_order(&mut self, allow_out_of_order: bool) {
+    self.allow_out_of_order = allow_out_of_order;
+  }
+
+  /// Makes sure there are no late motions, unless we were allowed to apply
+  /// them out of order. Only adding motions needs this, subbing leaves late
+  /// motions where they are.
+  pub fn check_order(&self) -> Result<(), Error> {
+    match self.state.late.first() {
+      Some(motion) if !self.allow_out_of_order => Err(error!("{}", late_message(motion))),
+      _ => Ok(()),
+    }
+  }
+
   pub fn add(&mut self, mut iterations: usize) -> Result<(), Error> {
+    try!(self.check_order());
     loop {
       // If we have finished our iterations break out.
       if iterations == 0 { break; }
       // Subtract one from our iterations.
       iterations -= 1;
-      // Pop off the next motion to be applied.
-      if let Some(motion) = self.state.unapplied.pop() {
-        // Read the contents of our motion file.
-        let mut file = try!(File::open(&motion.add_path));
-        let mut transaction = String::new();
-        try!(file.read_to_string(&mut transaction));
+      // Take the next motion to be applied. Late motions go first.
+      let motion = if self.state.late.is_empty() { self.state.unapplied.pop() } else { Some(self.state.late.remove(0)) };
+      if let Some(motion) = motion {
         // Execute the contents of our motion file.
-        try!(self.driver.execute(transaction));
+        try!(self.execute(&motion, &try!(motion.read_add())));
         // Add a record that we executed the motion.
-        try!(self.driver.add_record(&motion.name));
+        try!(self.driver.add_record(&Record { name: motion.name.clone(), checksum: Some(try!(motion.checksum())) }));
         // Print our success!
         println!("{} {}", "Add".green().bold(), motion);
         // Update our state to reflect that we’ve applied this motion.
@@ -56,6 +143,11 @@ impl Accelerator {
   }
 
   pub fn sub(&mut self, mut iterations: usize) -> Result<(), Error> {
+    // Make sure none of the motions we are about to sub are irreversible
+    // before we sub any of them.
+    if let Some(motion) = self.state.applied.iter().rev().take(iterations).find(|motion| motion.is_irreversible()) {
+      return Err(error!("The '{}' motion is irreversible so it can not be subbed. No motions were subbed.", motion));
+    }
     loop {
       // If we have finished our iterations break out.
       if iterations == 0 { break; }
@@ -63,12 +155,8 @@ impl Accelerator {
       iterations -= 1;
       // Pop off the next motion to be applied.
       if let Some(motion) = self.state.applied.pop() {
-        // Read the contents of our motion file.
-        let mut file = try!(File::open(&motion.sub_path));
-        let mut transaction = String::new();
-        try!(file.read_to_string(&mut transaction));
         // Execute the contents of our motion file.
-        try!(self.driver.execute(transaction));
+        try!(self.execute(&motion, &try!(motion.read_sub())));
         // Add a record that we executed the motion.
         try!(self.driver.sub_record(&motion.name));
         // Print our success!
@@ -84,9 +172,308 @@ impl Accelerator {
     Ok(())
   }
 
+  /// Executes every repeatable motion whose contents have changed since it was
+  /// last executed. If `force` is true they are all executed, changed or not.
+  pub fn repeat(&mut self, force: bool) -> Result<(), Error> {
+    for (motion, source, checksum) in try!(self.changed_repeatables(force)) {
+      try!(self.execute(&motion, &source));
+      try!(self.driver.set_repeatable_record(&Record { name: motion.name.clone(), checksum: Some(checksum) }));
+      println!("{} {}", "Repeat".cyan().bold(), motion);
+    }
+    Ok(())
+  }
+
+  /// Reads the repeatable motions which need to be executed along with their
+  /// current checksums.
+  fn changed_repeatables(&self, force: bool) -> Result<Vec<(Motion, Source, String)>, Error> {
+    let records = try!(self.driver.get_repeatable_records());
+    let mut changed = Vec::new();
+    for motion in self.repeatables.iter() {
+      let source = try!(motion.read_add());
+      let checksum = try!(motion.checksum());
+      if force || !records.iter().any(|record| record.name == motion.name && record.checksum.as_ref() == Some(&checksum)) {
+        changed.push((motion.clone(), source, checksum));
+      }
+    }
+    Ok(changed)
+  }
+
+  /// Executes every unapplied motion inside a transaction which is always
+  /// rolled back, proving the motions work without changing anything. If
+  /// `sub` is true the motions are subbed again afterwards.
+  pub fn check(&mut self, sub: bool) -> Result<(), Error> {
+    try!(self.check_order());
+    try!(self.driver.begin());
+    let result = self.check_unapplied(sub);
+    // No matter what happened, roll everything back. If the motions failed
+    // that error matters more than any from rolling back.
+    let result = result.and(self.driver.rollback());
+    if result.is_ok() {
+      println!("{} all changes", "Rollback".yellow().bold());
+    }
+    result
+  }
+
+  fn check_unapplied(&mut self, sub: bool) -> Result<(), Error> {
+    // Late motions go first, as they would with `add`. Our unapplied motions
+    // are in reverse order so the next one can be popped off the end.
+    let motions = self.state.late.iter().chain(self.state.unapplied.iter().rev()).cloned().collect::<Vec<Motion>>();
+    for motion in motions.iter() {
+      try!(self.execute(&motion, &try!(motion.read_add())));
+      println!("{} {}", "Add".green().bold(), motion);
+    }
+    for (motion, source, _) in try!(self.changed_repeatables(false)) {
+      try!(self.execute(&motion, &source));
+      println!("{} {}", "Repeat".cyan().bold(), motion);
+    }
+    if sub {
+      // Irreversible motions can’t be subbed, so we stop before the first one.
+      for motion in motions.iter().rev() {
+        if motion.is_irreversible() {
+          println!("{} {} is irreversible", "Stop".yellow().bold(), motion);
+          break;
+        }
+        try!(self.execute(&motion, &try!(motion.read_sub())));
+        println!("{} {}", "Sub".red().bold(), motion);
+      }
+    }
+    Ok(())
+  }
+
   pub fn applied_count(&self) -> usize {
     self.state.applied.len()
   }
+
+  pub fn unapplied_count(&self) -> usize {
+    self.state.unapplied.len() + self.state.late.len()
+  }
+
+  pub fn late_count(&self) -> usize {
+    self.state.late.len()
+  }
+
+  pub fn dump_schema(&self) -> Result<String, Error> {
+    self.driver.dump_schema()
+  }
+
+  pub fn into_driver(self) -> Box<Driver> {
+    self.driver
+  }
+
+  fn execute(&mut self, motion: &Motion, source: &Source) -> Result<(), Error> {
+    let options = ExecuteOptions {
+      transaction: motion.meta.transaction.unwrap_or(false),
+      timeout: motion.meta.timeout,
+    };
+    // Fill in the placeholders in our source, then execute it, pointing to
+    // where in the file things went wrong if the driver fails.
+    let result = self.variables.substitute_if(&source.full_text(), motion.meta.variables).and_then(|substitution| {
+      self.driver.execute(substitution.text.clone(), &options).map_err(|error| {
+        // The driver only knows positions in the substituted text.
+        match error.downcast::<QueryError>() {
+          Ok(error) => Box::new(QueryError { position: substitution.original_position(error.position), error: error.error }) as Error,
+          Err(error) => error,
+        }
+      })
+    });
+    match result {
+      Ok(()) => Ok(()),
+      Err(error) => {
+        let summary = motion.meta.summary();
+        Err(SourceError::new(source, if summary.is_empty() { None } else { Some(summary) }, error))
+      },
+    }
+  }
+}
+
+/// Renames every record of a motion which has since been renamed, unless the
+/// motion already has a record under its new name.
+fn rename_records(records: &mut Vec<Record>, repeatable_records: &mut Vec<Record>, motions: &[Motion], aliases: &Aliases) -> Vec<Change> {
+  let names = records.iter().chain(repeatable_records.iter()).map(|record| record.name.clone()).collect::<Vec<String>>();
+  let mut changes = Vec::new();
+  for record in records.iter_mut().chain(repeatable_records.iter_mut()) {
+    if motions.iter().any(|motion| motion.name == record.name) { continue; }
+    let new_name = aliases.resolve(&record.name).to_string();
+    if names.contains(&new_name) { continue; }
+    if let Some(motion) = motions.iter().find(|motion| motion.name == new_name) {
+      changes.push(Change::Rename {
+        old_name: record.name.clone(),
+        new_name: new_name.clone(),
+        message: format!("{} to {}", record.name, motion),
+      });
+      record.name = new_name;
+    }
+  }
+  changes
+}
+
+/// Replaces the records of motions which were squashed together with a record
+/// of the motion they were squashed into. A database which applied only some
+/// of them can’t be brought up to date this way.
+fn squash_records(records: &mut Vec<Record>, motions: &[Motion], aliases: &Aliases) -> Result<Vec<Change>, Error> {
+  let mut changes = Vec::new();
+  // A motion may be squashed again into a later one, so we keep going until
+  // there is nothing left to squash.
+  loop {
+    let mut change = None;
+    for (name, squashed) in aliases.squashes().iter() {
+      if records.iter().any(|record| &record.name == name) { continue; }
+      let motion = motions.iter().find(|motion| &motion.name == name);
+      // Find the record of every squashed motion, even those recorded under a
+      // name they had before being renamed.
+      let squashed_records = squashed.iter().map(|squashed| {
+        records.iter().find(|record| &record.name == squashed || aliases.resolve(&record.name) == squashed)
+      }).collect::<Vec<Option<&Record>>>();
+      if squashed_records.iter().all(Option::is_none) { continue; }
+      if squashed_records.iter().any(Option::is_none) {
+        // Only complain about the motions we still have, records of older
+        // squashes may be waiting on a later one.
+        let motion = match motion { Some(motion) => motion, None => continue };
+        let missing = squashed.iter().zip(squashed_records.iter()).filter(|&(_, record)| record.is_none()).map(|(squashed, _)| squashed.as_str()).collect::<Vec<&str>>();
+        return Err(error!(
+          "Only some of the motions squashed into the '{}' motion were applied, these were not: {}. Apply them by hand before the squashed motion can be recorded.",
+          motion,
+          missing.join(", ")
+        ));
+      }
+      let checksum = match motion { Some(motion) => Some(try!(motion.checksum())), None => None };
+      change = Some(Change::Squash {
+        names: squashed_records.into_iter().filter_map(|record| record).map(|record| record.name.clone()).collect(),
+        record: Record { name: name.clone(), checksum: checksum },
+        message: motion.map(|motion| format!("{} into {}", squashed.join(", "), motion)),
+      });
+      break;
+    }
+    match change {
+      Some(change) => {
+        if let Change::Squash { ref names, ref record, .. } = change {
+          records.retain(|other| !names.contains(&other.name));
+          records.push(record.clone());
+        }
+        changes.push(change);
+      },
+      None => return Ok(changes),
+    }
+  }
+}
+
+/// Records the motions which were just squashed under the name of the motion
+/// they were squashed into, all in one transaction. Unlike building an
+/// accelerator this looks at nothing but the records of squashed motions.
+pub fn record_squashes(driver: &mut Driver, motions: &[Motion], aliases: &Aliases) -> Result<(), Error> {
+  let mut records = try!(driver.get_records());
+  let changes = try!(squash_records(&mut records, motions, aliases));
+  write_changes_in_transaction(driver, &changes)
+}
+
+fn write_changes_in_transaction(driver: &mut Driver, changes: &[Change]) -> Result<(), Error> {
+  if changes.is_empty() { return Ok(()); }
+  try!(driver.begin());
+  match write_changes(driver, changes) {
+    Ok(()) => driver.commit(),
+    Err(error) => {
+      // The error which made us roll back matters more than any from rolling
+      // back.
+      let _ = driver.rollback();
+      Err(error)
+    },
+  }
+}
+
+/// Finds the names of the records which belong to no namespace but are of
+/// motions in ours, and which we have no record of ourselves.
+pub fn find_adoptable(driver: &Driver, motions: &[Motion], aliases: &Aliases) -> Result<Vec<String>, Error> {
+  let names = try!(driver.get_records()).into_iter().chain(try!(driver.get_repeatable_records())).map(|record| aliases.resolve(&record.name).to_string()).collect::<Vec<String>>();
+  Ok(try!(driver.get_records_without_namespace()).into_iter().map(|record| record.name).filter(|name| {
+    let name = aliases.resolve(name);
+    motions.iter().any(|motion| motion.name == name) && !names.iter().any(|other| other == name)
+  }).collect())
+}
+
+/// Makes changes to the records of a database.
+fn write_changes(driver: &mut Driver, changes: &[Change]) -> Result<(), Error> {
+  for change in changes.iter() {
+    match *change {
+      Change::Rename { ref old_name, ref new_name, ref message } => {
+        try!(driver.rename_record(old_name, new_name));
+        println!("{} {}", "Rename".blue().bold(), message);
+      },
+      Change::Squash { ref names, ref record, ref message } => {
+        for name in names.iter() {
+          try!(driver.sub_record(name));
+        }
+        try!(driver.add_record(record));
+        if let Some(ref message) = *message {
+          println!("{} {}", "Squash".blue().bold(), message);
+        }
+      },
+    }
+  }
+  Ok(())
+}
+
+/// Finds the motions which have not been applied but come before motions which
+/// have, in order. Records of motions we don’t know of are left out.
+pub fn find_late(records: &[Record], motions: &[Motion]) -> Result<Vec<Motion>, Error> {
+  let motions = motions.iter().filter(|motion| !motion.repeatable).cloned().collect::<Vec<Motion>>();
+  let names = records.iter().filter(|record| motions.iter().any(|motion| motion.name == record.name)).map(|record| record.name.clone()).collect();
+  Ok(try!(diff_motions(names, motions)).late)
+}
+
+/// Where a motion stands against the records of a database.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Status {
+  Applied,
+  /// Applied, but the motion has changed since.
+  Changed,
+  /// Not applied, but comes before motions which have been.
+  Late,
+  Unapplied,
+  /// A repeatable motion which was last executed as it is now.
+  Repeated,
+  /// A repeatable motion which has changed since it was last executed, or was
+  /// never executed at all.
+  Outdated,
+}
+
+/// Finds where every motion stands, in the same order as the motions. Unlike
+/// building an accelerator this never fails because a motion changed, so we
+/// can report on it. Records must already have the new names of renamed
+/// motions.
+pub fn find_statuses(records: &[Record], repeatable_records: &[Record], motions: &[Motion]) -> Result<Vec<Status>, Error> {
+  let late = try!(find_late(records, motions));
+  let mut statuses = Vec::new();
+  for motion in motions.iter() {
+    statuses.push(if motion.repeatable {
+      let checksum = try!(motion.checksum());
+      if repeatable_records.iter().any(|record| record.name == motion.name && record.checksum.as_ref() == Some(&checksum)) { Status::Repeated } else { Status::Outdated }
+    } else {
+      match records.iter().find(|record| record.name == motion.name) {
+        Some(record) if try!(has_changed(motion, record)) => Status::Changed,
+        Some(_) => Status::Applied,
+        None if late.contains(motion) => Status::Late,
+        None => Status::Unapplied,
+      }
+    });
+  }
+  Ok(statuses)
+}
+
+/// Explains that a motion is late and how to apply it anyway.
+pub fn late_message(motion: &Motion) -> String {
+  format!(
+    "The '{}' motion has not been applied but comes before motions which have been. If it arrived in a merge, apply it with `accelerate up --allow-out-of-order`.",
+    motion
+  )
+}
+
+/// Whether a motion has changed since it was recorded. Records from before we
+/// kept checksums can’t tell us, so we assume those have not.
+pub fn has_changed(motion: &Motion, record: &Record) -> Result<bool, Error> {
+  match record.checksum {
+    Some(ref checksum) => Ok(*checksum != try!(motion.checksum())),
+    None => Ok(false),
+  }
 }
 
 fn diff_motions(mut motion_names: Vec<String>, mut motions: Vec<Motion>) -> Result<State, Error> {
@@ -103,8 +490,14 @@ fn diff_motions(mut motion_names: Vec<String>, mut motions: Vec<Motion>) -> Resu
   let mut state = State {
     applied: Vec::new(),
     unapplied: Vec::new(),
+    late: Vec::new(),
   };
 
+  // Records come back in no particular order, but they should have been
+  // applied in the same order as our motions. Put them in that order so we can
+  // compare them one by one. Records we don’t know of go to the end.
+  motion_names.sort_by_key(|name| (motions.iter().position(|motion| &motion.name == name).unwrap_or(usize::max_value()), name.clone()));
+
   // We have an index for error reporting purposes. The index starts at -1
   // because we increment when the loop starts so it will then become 0.
   let mut index = -1;
@@ -127,6 +520,13 @@ fn diff_motions(mut motion_names: Vec<String>, mut motions: Vec<Motion>) -> Resu
         if motion_name == motion.name {
           state.applied.push(motion);
         }
+        // If the motion we expected was never applied but the applied motion
+        // comes later, the motion we expected is late. We will compare the
+        // applied motion with the next motion we expect.
+        else if !motion_names.contains(&motion.name) && motions.iter().any(|motion| motion.name == motion_name) {
+          state.late.push(motion);
+          motion_names.push(motion_name);
+        }
         // Otherwise, if the motions are different, something bad happened.
         // We should let the user know.
         else {
@@ -161,18 +561,34 @@ mod tests {
   use std::mem;
   use std::path::{Path, PathBuf};
   use motions::Motion;
+  use driver::Record;
   use driver::test::TestDriver;
-  use super::{State, diff_motions, Accelerator};
+  use checksum::checksum;
+  use variables::Variables;
+  use aliases::Aliases;
+  use super::{State, diff_motions, find_late, find_statuses, Status, rename_records, squash_records, Accelerator};
 
   fn pb(path: &str) -> PathBuf {
     Path::new(path).to_path_buf()
   }
 
+  fn record(name: &str) -> Record {
+    Record {
+      name: name.to_string(),
+      checksum: None,
+    }
+  }
+
+  fn names(records: &[Record]) -> Vec<String> {
+    records.iter().map(|record| record.name.clone()).collect()
+  }
+
   fn motion_a() -> Motion {
     Motion {
       name: "a".to_string(),
       add_path: pb("a.add"),
-      sub_path: pb("a.sub"),
+      sub_path: Some(pb("a.sub")),
+      ..Motion::default()
     }
   }
 
@@ -180,7 +596,8 @@ mod tests {
     Motion {
       name: "b".to_string(),
       add_path: pb("b.add"),
-      sub_path: pb("b.sub"),
+      sub_path: Some(pb("b.sub")),
+      ..Motion::default()
     }
   }
 
@@ -188,7 +605,8 @@ mod tests {
     Motion {
       name: "c".to_string(),
       add_path: pb("c.add"),
-      sub_path: pb("c.sub"),
+      sub_path: Some(pb("c.sub")),
+      ..Motion::default()
     }
   }
 
@@ -196,7 +614,8 @@ mod tests {
     Motion {
       name: "123456-foo".to_string(),
       add_path: pb("tests/fixtures/basic/123456-foo.add"),
-      sub_path: pb("tests/fixtures/basic/123456-foo.sub"),
+      sub_path: Some(pb("tests/fixtures/basic/123456-foo.sub")),
+      ..Motion::default()
     }
   }
 
@@ -204,7 +623,8 @@ mod tests {
     Motion {
       name: "234567-bar".to_string(),
       add_path: pb("tests/fixtures/basic/234567-bar.add"),
-      sub_path: pb("tests/fixtures/basic/234567-bar.sub"),
+      sub_path: Some(pb("tests/fixtures/basic/234567-bar.sub")),
+      ..Motion::default()
     }
   }
 
@@ -215,7 +635,27 @@ mod tests {
 
   #[test]
   fn test_diff_motions_unequal() {
-    assert!(diff_motions(vec!["c".to_string(), "a".to_string()], vec![motion_a(), motion_b(), motion_c()]).is_err());
+    assert!(diff_motions(vec!["d".to_string(), "a".to_string()], vec![motion_a(), motion_b(), motion_c()]).is_err());
+  }
+
+  #[test]
+  fn test_diff_motions_late() {
+    assert_eq!(diff_motions(
+      vec!["c".to_string()],
+      vec![motion_a(), motion_b(), motion_c()]
+    ).unwrap(), State {
+      applied: vec![motion_c()],
+      unapplied: vec![],
+      late: vec![motion_a(), motion_b()],
+    });
+    assert_eq!(diff_motions(
+      vec!["b".to_string()],
+      vec![motion_a(), motion_b(), motion_c()]
+    ).unwrap(), State {
+      applied: vec![motion_b()],
+      unapplied: vec![motion_c()],
+      late: vec![motion_a()],
+    });
   }
 
   #[test]
@@ -226,6 +666,7 @@ mod tests {
     ).unwrap(), State {
       applied: vec![motion_a(), motion_b(), motion_c()],
       unapplied: vec![],
+      late: vec![],
     });
   }
 
@@ -237,6 +678,7 @@ mod tests {
     ).unwrap(), State {
       applied: vec![motion_a(), motion_b()],
       unapplied: vec![motion_c()],
+      late: vec![],
     });
     assert_eq!(diff_motions(
       vec!["a".to_string()],
@@ -244,6 +686,19 @@ mod tests {
     ).unwrap(), State {
       applied: vec![motion_a()],
       unapplied: vec![motion_c(), motion_b()],
+      late: vec![],
+    });
+  }
+
+  #[test]
+  fn test_diff_motions_unordered_names() {
+    assert_eq!(diff_motions(
+      vec!["b".to_string(), "a".to_string()],
+      vec![motion_a(), motion_b(), motion_c()]
+    ).unwrap(), State {
+      applied: vec![motion_a(), motion_b()],
+      unapplied: vec![motion_c()],
+      late: vec![],
     });
   }
 
@@ -255,6 +710,7 @@ mod tests {
     ).unwrap(), State {
       applied: vec![],
       unapplied: vec![motion_c(), motion_b(), motion_a()],
+      late: vec![],
     });
   }
 
@@ -264,18 +720,27 @@ mod tests {
       driver: Box::new(TestDriver {
         records: vec![],
         executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
       }),
+      repeatables: vec![],
       state: State {
         applied: vec![],
         unapplied: vec![motion_foo(), motion_bar()],
+        late: vec![],
       },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
     };
 
     accelerator.add(1).unwrap();
 
     let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
 
-    assert_eq!(driver.records, vec!["234567-bar".to_string()]);
+    assert_eq!(names(&driver.records), vec!["234567-bar".to_string()]);
+    assert_eq!(driver.records[0].checksum, Some(checksum("bar+\nbar-\n")));
     assert_eq!(driver.executions, vec!["bar+\n".to_string()]);
     assert_eq!(accelerator.state.applied, vec![motion_bar()]);
     assert_eq!(accelerator.state.unapplied, vec![motion_foo()]);
@@ -287,18 +752,26 @@ mod tests {
       driver: Box::new(TestDriver {
         records: vec![],
         executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
       }),
+      repeatables: vec![],
       state: State {
         applied: vec![],
         unapplied: vec![motion_foo(), motion_bar()],
+        late: vec![],
       },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
     };
 
     accelerator.add(2).unwrap();
 
     let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
 
-    assert_eq!(driver.records, vec!["234567-bar".to_string(), "123456-foo".to_string()]);
+    assert_eq!(names(&driver.records), vec!["234567-bar".to_string(), "123456-foo".to_string()]);
     assert_eq!(driver.executions, vec!["bar+\n".to_string(), "foo+\n".to_string()]);
     assert_eq!(accelerator.state.applied, vec![motion_bar(), motion_foo()]);
     assert_eq!(accelerator.state.unapplied, vec![]);
@@ -310,18 +783,26 @@ mod tests {
       driver: Box::new(TestDriver {
         records: vec![],
         executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
       }),
+      repeatables: vec![],
       state: State {
         applied: vec![],
         unapplied: vec![motion_foo(), motion_bar()],
+        late: vec![],
       },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
     };
 
     accelerator.add(3).unwrap();
 
     let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
 
-    assert_eq!(driver.records, vec!["234567-bar".to_string(), "123456-foo".to_string()]);
+    assert_eq!(names(&driver.records), vec!["234567-bar".to_string(), "123456-foo".to_string()]);
     assert_eq!(driver.executions, vec!["bar+\n".to_string(), "foo+\n".to_string()]);
     assert_eq!(accelerator.state.applied, vec![motion_bar(), motion_foo()]);
     assert_eq!(accelerator.state.unapplied, vec![]);
@@ -331,20 +812,28 @@ mod tests {
   fn test_accelerator_sub_1() {
     let mut accelerator = Accelerator {
       driver: Box::new(TestDriver {
-        records: vec!["234567-bar".to_string(), "123456-foo".to_string()],
+        records: vec![record("234567-bar"), record("123456-foo")],
         executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
       }),
+      repeatables: vec![],
       state: State {
         applied: vec![motion_bar(), motion_foo()],
         unapplied: vec![],
+        late: vec![],
       },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
     };
 
     accelerator.sub(1).unwrap();
 
     let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
 
-    assert_eq!(driver.records, vec!["234567-bar".to_string()]);
+    assert_eq!(names(&driver.records), vec!["234567-bar".to_string()]);
     assert_eq!(driver.executions, vec!["foo-\n".to_string()]);
     assert_eq!(accelerator.state.applied, vec![motion_bar()]);
     assert_eq!(accelerator.state.unapplied, vec![motion_foo()]);
@@ -354,20 +843,28 @@ mod tests {
   fn test_accelerator_sub_2() {
     let mut accelerator = Accelerator {
       driver: Box::new(TestDriver {
-        records: vec!["234567-bar".to_string(), "123456-foo".to_string()],
+        records: vec![record("234567-bar"), record("123456-foo")],
         executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
       }),
+      repeatables: vec![],
       state: State {
         applied: vec![motion_bar(), motion_foo()],
         unapplied: vec![],
+        late: vec![],
       },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
     };
 
     accelerator.sub(2).unwrap();
 
     let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
 
-    assert_eq!(driver.records, vec![] as Vec<String>);
+    assert_eq!(names(&driver.records), vec![] as Vec<String>);
     assert_eq!(driver.executions, vec!["foo-\n".to_string(), "bar-\n".to_string()]);
     assert_eq!(accelerator.state.applied, vec![] as Vec<Motion>);
     assert_eq!(accelerator.state.unapplied, vec![motion_foo(), motion_bar()]);
@@ -377,22 +874,238 @@ mod tests {
   fn test_accelerator_sub_3() {
     let mut accelerator = Accelerator {
       driver: Box::new(TestDriver {
-        records: vec!["234567-bar".to_string(), "123456-foo".to_string()],
+        records: vec![record("234567-bar"), record("123456-foo")],
         executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
       }),
+      repeatables: vec![],
       state: State {
         applied: vec![motion_bar(), motion_foo()],
         unapplied: vec![],
+        late: vec![],
       },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
     };
 
     accelerator.sub(3).unwrap();
 
     let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
 
-    assert_eq!(driver.records, vec![] as Vec<String>);
+    assert_eq!(names(&driver.records), vec![] as Vec<String>);
     assert_eq!(driver.executions, vec!["foo-\n".to_string(), "bar-\n".to_string()]);
     assert_eq!(accelerator.state.applied, vec![] as Vec<Motion>);
     assert_eq!(accelerator.state.unapplied, vec![motion_foo(), motion_bar()]);
   }
+
+  #[test]
+  fn test_accelerator_add_late() {
+    let mut accelerator = Accelerator {
+      driver: Box::new(TestDriver {
+        records: vec![record("234567-bar")],
+        executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
+      }),
+      repeatables: vec![],
+      state: State {
+        applied: vec![motion_bar()],
+        unapplied: vec![],
+        late: vec![motion_foo()],
+      },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
+    };
+
+    assert!(accelerator.add(1).is_err());
+    assert!(accelerator.check_order().is_err());
+
+    accelerator.set_allow_out_of_order(true);
+    accelerator.add(1).unwrap();
+
+    let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
+
+    assert_eq!(names(&driver.records), vec!["234567-bar".to_string(), "123456-foo".to_string()]);
+    assert_eq!(driver.executions, vec!["foo+\n".to_string()]);
+    assert_eq!(accelerator.state.applied, vec![motion_bar(), motion_foo()]);
+    assert_eq!(accelerator.state.late, vec![] as Vec<Motion>);
+  }
+
+  #[test]
+  fn test_accelerator_sub_late() {
+    let mut accelerator = Accelerator {
+      driver: Box::new(TestDriver {
+        records: vec![record("234567-bar")],
+        executions: vec![],
+        repeatable_records: vec![],
+        transaction: None,
+        ..TestDriver::default()
+      }),
+      repeatables: vec![],
+      state: State {
+        applied: vec![motion_bar()],
+        unapplied: vec![],
+        late: vec![motion_foo()],
+      },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
+    };
+
+    accelerator.sub(1).unwrap();
+
+    let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
+
+    assert_eq!(names(&driver.records), vec![] as Vec<String>);
+    assert_eq!(driver.executions, vec!["bar-\n".to_string()]);
+    assert_eq!(accelerator.state.late, vec![motion_foo()]);
+  }
+
+  #[test]
+  fn test_accelerator_new_keep() {
+    let driver = TestDriver {
+      records: vec![record("a"), record("c")],
+      ..TestDriver::default()
+    };
+    let accelerator = Accelerator::new(Box::new(driver), vec![motion_a(), motion_b(), motion_c()], Variables::default(), &Aliases::default(), |motion| motion.name != "b").unwrap();
+    assert_eq!(accelerator.state, State {
+      applied: vec![motion_a(), motion_c()],
+      unapplied: vec![],
+      late: vec![],
+    });
+    let driver = TestDriver {
+      records: vec![record("a"), record("b")],
+      ..TestDriver::default()
+    };
+    let accelerator = Accelerator::new(Box::new(driver), vec![motion_a(), motion_b(), motion_c()], Variables::default(), &Aliases::default(), |motion| motion.name != "b").unwrap();
+    assert_eq!(accelerator.state, State {
+      applied: vec![motion_a()],
+      unapplied: vec![motion_c()],
+      late: vec![],
+    });
+  }
+
+  #[test]
+  fn test_find_late() {
+    assert_eq!(find_late(&[record("c"), record("gone")], &[motion_a(), motion_b(), motion_c()]).unwrap(), vec![motion_a(), motion_b()]);
+    assert_eq!(find_late(&[record("a")], &[motion_a(), motion_b(), motion_c()]).unwrap(), vec![] as Vec<Motion>);
+  }
+
+  #[test]
+  fn test_find_statuses() {
+    assert_eq!(
+      find_statuses(&[record("b")], &[], &[motion_a(), motion_b(), motion_c()]).unwrap(),
+      vec![Status::Late, Status::Applied, Status::Unapplied]
+    );
+    let changed = Record { name: "123456-foo".to_string(), checksum: Some("nope".to_string()) };
+    assert_eq!(find_statuses(&[changed], &[], &[motion_foo(), motion_bar()]).unwrap(), vec![Status::Changed, Status::Unapplied]);
+  }
+
+  #[test]
+  fn test_rename_records() {
+    let mut aliases = Aliases::default();
+    aliases.set("000000-old", "123456-foo");
+    aliases.set("000000-gone", "999999-nope");
+    let mut records = vec![record("000000-old"), record("000000-gone"), record("234567-bar")];
+    let changes = rename_records(&mut records, &mut vec![], &[motion_foo(), motion_bar()], &aliases);
+    assert_eq!(names(&records), vec!["123456-foo".to_string(), "000000-gone".to_string(), "234567-bar".to_string()]);
+    assert_eq!(changes.len(), 1);
+  }
+
+  #[test]
+  fn test_accelerator_reconcile_records() {
+    let mut aliases = Aliases::default();
+    aliases.set("000000-old", "123456-foo");
+    let driver = TestDriver {
+      records: vec![record("000000-old")],
+      ..TestDriver::default()
+    };
+    let mut accelerator = Accelerator::new(Box::new(driver), vec![motion_foo(), motion_bar()], Variables::default(), &aliases, |_| true).unwrap();
+    assert_eq!(accelerator.state.applied, vec![motion_foo()]);
+
+    {
+      let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
+      assert_eq!(names(&driver.records), vec!["000000-old".to_string()]);
+    }
+
+    accelerator.reconcile_records().unwrap();
+
+    let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
+
+    assert_eq!(names(&driver.records), vec!["123456-foo".to_string()]);
+    assert_eq!(driver.transaction, None);
+  }
+
+  #[test]
+  fn test_squash_records() {
+    let baseline = Motion {
+      name: "234567-baseline".to_string(),
+      add_path: pb("tests/fixtures/squash/234567-baseline.add"),
+      sub_path: Some(pb("tests/fixtures/squash/234567-baseline.sub")),
+      ..Motion::default()
+    };
+    let mut aliases = Aliases::default();
+    aliases.set("100000-old", "123456-foo");
+    aliases.set_squash("200000-first", vec!["100000-a".to_string(), "100001-b".to_string()]);
+    aliases.set_squash("234567-baseline", vec!["200000-first".to_string(), "123456-foo".to_string(), "234567-bar".to_string()]);
+    let mut records = vec![record("100000-a"), record("100001-b"), record("100000-old"), record("234567-bar"), record("345678-baz")];
+    let changes = squash_records(&mut records, &[baseline.clone()], &aliases).unwrap();
+    assert_eq!(names(&records), vec!["345678-baz".to_string(), "234567-baseline".to_string()]);
+    assert_eq!(records[1].checksum, Some(checksum("foo+\nbar+\nbar-\nfoo-\n")));
+    assert_eq!(changes.len(), 2);
+    let mut records = vec![record("234567-bar")];
+    assert!(squash_records(&mut records, &[baseline], &aliases).is_err());
+  }
+
+  #[test]
+  fn test_accelerator_repeat() {
+    let views = Motion {
+      name: "views".to_string(),
+      add_path: pb("tests/fixtures/repeatable/views.always"),
+      repeatable: true,
+      ..Motion::default()
+    };
+    let grants = Motion {
+      name: "grants".to_string(),
+      add_path: pb("tests/fixtures/repeatable/grants.always"),
+      repeatable: true,
+      ..Motion::default()
+    };
+    let mut accelerator = Accelerator {
+      driver: Box::new(TestDriver {
+        records: vec![],
+        executions: vec![],
+        repeatable_records: vec![Record { name: "views".to_string(), checksum: Some(checksum("views+\n")) }],
+        transaction: None,
+        ..TestDriver::default()
+      }),
+      repeatables: vec![views, grants],
+      state: State {
+        applied: vec![],
+        unapplied: vec![],
+        late: vec![],
+      },
+      variables: Variables::default(),
+      allow_out_of_order: false,
+      changes: vec![],
+    };
+
+    accelerator.repeat(false).unwrap();
+
+    let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
+
+    assert_eq!(driver.executions, vec!["-- requires: views\n\ngrants+\n".to_string()]);
+    assert_eq!(driver.repeatable_records.len(), 2);
+
+    accelerator.repeat(true).unwrap();
+
+    let driver: &Box<TestDriver> = unsafe { mem::transmute(&accelerator.driver) };
+
+    assert_eq!(driver.executions.len(), 3);
+  }
 }
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use config::Config;
+
+/// The old names of motions which have been renamed, read from lines like
+/// `alias.123456-old = 123456-new` in the config file, and the motions which
+/// were squashed into a baseline, from lines like `squash.234567-baseline =
+/// 123456-foo, 234567-bar`. Records with an old name are renamed the next time
+/// we see them.
+#[derive(Eq, PartialEq, Default, Debug)]
+pub struct Aliases {
+  names: BTreeMap<String, String>,
+  squashes: BTreeMap<String, Vec<String>>,
+}
+
+impl Aliases {
+  pub fn from_config(config: &Config) -> Aliases {
+    let mut aliases = Aliases::default();
+    for (old_name, new_name) in config.get_prefixed("alias.") {
+      aliases.set(old_name, new_name);
+    }
+    for (name, squashed) in config.get_prefixed("squash.") {
+      aliases.set_squash(name, squashed.split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect());
+    }
+    aliases
+  }
+
+  pub fn set(&mut self, old_name: &str, new_name: &str) {
+    self.names.insert(old_name.to_string(), new_name.to_string());
+  }
+
+  /// Records that some motions were squashed into a single motion.
+  pub fn set_squash(&mut self, name: &str, squashed: Vec<String>) {
+    self.squashes.insert(name.to_string(), squashed);
+  }
+
+  /// Every motion which others were squashed into, with the names of those it
+  /// replaced.
+  pub fn squashes(&self) -> &BTreeMap<String, Vec<String>> {
+    &self.squashes
+  }
+
+  /// Finds the current name for a motion, following every rename since it had
+  /// this name. A name which was never renamed is its own current name.
+  pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+    let mut current = name;
+    // A motion renamed back to an old name would send us in circles, so we
+    // never take more steps than there are aliases.
+    for _ in 0..self.names.len() {
+      match self.names.get(current) {
+        Some(new_name) => current = new_name,
+        None => break,
+      }
+    }
+    current
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use config::Config;
+  use super::Aliases;
+
+  #[test]
+  fn test_resolve() {
+    let mut aliases = Aliases::default();
+    aliases.set("a", "b");
+    aliases.set("b", "c");
+    aliases.set("x", "y");
+    assert_eq!(aliases.resolve("a"), "c");
+    assert_eq!(aliases.resolve("b"), "c");
+    assert_eq!(aliases.resolve("c"), "c");
+    assert_eq!(aliases.resolve("x"), "y");
+    assert_eq!(aliases.resolve("nope"), "nope");
+  }
+
+  #[test]
+  fn test_from_config() {
+    let dir = Path::new("tests/fixtures/squash");
+    let aliases = Aliases::from_config(&Config::read(dir).unwrap());
+    assert_eq!(aliases.resolve("100000-old"), "123456-foo");
+    assert_eq!(aliases.squashes().get("234567-baseline"), Some(&vec!["123456-foo".to_string(), "234567-bar".to_string()]));
+  }
+
+  #[test]
+  fn test_resolve_cycle() {
+    let mut aliases = Aliases::default();
+    aliases.set("a", "b");
+    aliases.set("b", "a");
+    assert_eq!(aliases.resolve("a"), "a");
+    assert_eq!(aliases.resolve("b"), "b");
+  }
+}
@@ -0,0 +1,24 @@
+/// Hashes some text into a short hexadecimal string which changes whenever the
+/// text does. We use 64 bit FNV-1a because it is tiny and stable across
+/// versions of Rust, unlike the standard library’s hashers.
+pub fn checksum(text: &str) -> String {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in text.bytes() {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::checksum;
+
+  #[test]
+  fn test_checksum() {
+    assert_eq!(checksum(""), "cbf29ce484222325");
+    assert_eq!(checksum("a"), "af63dc4c8601ec8c");
+    assert_eq!(checksum("foo+\n"), checksum("foo+\n"));
+    assert!(checksum("foo+\n") != checksum("foo+\r\n"));
+  }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::fs::{File, OpenOptions};
+use error::Error;
+
+/// The name of the file in the motions directory which configures a project.
+pub const FILE_NAME: &'static str = "accelerate.conf";
+
+/// Settings for a project, read from lines like `key = value` in its config
+/// file. Blank lines and lines starting with `#` are ignored.
+#[derive(Eq, PartialEq, Default, Debug)]
+pub struct Config {
+  values: BTreeMap<String, String>,
+}
+
+impl Config {
+  /// Reads the config file in a motions directory. A directory without one
+  /// has an empty config.
+  pub fn read(dir: &Path) -> Result<Config, Error> {
+    let path = dir.join(FILE_NAME);
+    let mut text = String::new();
+    match File::open(&path) {
+      Ok(mut file) => { try!(file.read_to_string(&mut text)); },
+      Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+      Err(error) => return Err(Box::new(error)),
+    }
+    Config::parse(&text).map_err(|error| error!("Invalid config file '{}': {}", path.display(), error))
+  }
+
+  /// Adds a line setting a key to the config file in a motions directory,
+  /// creating the file if there isn’t one. Everything already in the file is
+  /// left as it is.
+  pub fn append(dir: &Path, key: &str, value: &str) -> Result<(), Error> {
+    let path = dir.join(FILE_NAME);
+    let mut text = String::new();
+    match File::open(&path) {
+      Ok(mut file) => { try!(file.read_to_string(&mut text)); },
+      Err(ref error) if error.kind() == ErrorKind::NotFound => (),
+      Err(error) => return Err(Box::new(error)),
+    }
+    let mut file = try!(OpenOptions::new().create(true).append(true).open(&path));
+    // Don’t join our line onto a last line without a newline.
+    if !text.is_empty() && !text.ends_with("\n") { try!(file.write_all(b"\n")); }
+    try!(writeln!(file, "{} = {}", key, value));
+    Ok(())
+  }
+
+  fn parse(text: &str) -> Result<Config, Error> {
+    let mut values = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with("#") { continue; }
+      let mut parts = line.splitn(2, '=');
+      match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+        (Some(key), Some(value)) if !key.is_empty() => { values.insert(key.to_string(), value.to_string()); },
+        _ => return Err(error!("Expected a line like `key = value` on line {} but found '{}'.", i + 1, line)),
+      }
+    }
+    Ok(Config { values: values })
+  }
+
+  /// Gets the value for a key, if it was set.
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.values.get(key).map(String::as_str)
+  }
+
+  /// Gets every value whose key starts with a prefix, like `var.`, with the
+  /// prefix removed from the keys.
+  pub fn get_prefixed(&self, prefix: &str) -> Vec<(&str, &str)> {
+    self.values.iter().filter(|&(key, _)| key.starts_with(prefix)).map(|(key, value)| (&key[prefix.len()..], value.as_str())).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use std::path::Path;
+  use super::Config;
+
+  #[test]
+  fn test_parse() {
+    let config = Config::parse("# Our project.\nfoo = bar\n\nvar.schema = app = 1\nvar.role=admin\n").unwrap();
+    assert_eq!(config.get("foo"), Some("bar"));
+    assert_eq!(config.get("nope"), None);
+    assert_eq!(config.get_prefixed("var."), vec![("role", "admin"), ("schema", "app = 1")]);
+  }
+
+  #[test]
+  fn test_parse_invalid() {
+    assert!(Config::parse("foo\n").is_err());
+    assert!(Config::parse(" = bar\n").is_err());
+  }
+
+  #[test]
+  fn test_append() {
+    let dir = env::temp_dir().join("accelerate-test-config-append");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    Config::append(&dir, "foo", "bar").unwrap();
+    Config::append(&dir, "alias.a", "b").unwrap();
+    let config = Config::read(&dir).unwrap();
+    assert_eq!(config.get("foo"), Some("bar"));
+    assert_eq!(config.get("alias.a"), Some("b"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_read_missing() {
+    assert_eq!(Config::read(Path::new("tests/fixtures/basic")).unwrap(), Config::default());
+  }
+}
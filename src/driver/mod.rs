@@ -3,13 +3,83 @@ pub mod test;
 #[cfg(feature = "driver-postgres")]
 pub mod postgres;
 
+use std::time::Duration;
 use error::Error;
 
+/// Where a driver should look for the tenants, each with its own copy of the
+/// schema, that motions are applied to.
+pub enum Tenants {
+  /// Every tenant with a name matching a pattern.
+  Pattern(String),
+  /// Every tenant named by a query.
+  Query(String),
+}
+
+/// Options for how a driver should execute a query.
+#[derive(Default, Debug)]
+pub struct ExecuteOptions {
+  /// Execute the query in a transaction of its own.
+  pub transaction: bool,
+  /// Cancel the query if it takes longer than this.
+  pub timeout: Option<Duration>,
+}
+
+/// A record of a motion being applied along with the checksum of its contents
+/// at the time. Motions recorded before we kept checksums have none.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Record {
+  pub name: String,
+  pub checksum: Option<String>,
+}
+
 pub trait Driver {
-  fn get_records(&self) -> Result<Vec<String>, Error>;
-  fn add_record(&mut self, record: &str) -> Result<(), Error>;
+  fn get_records(&self) -> Result<Vec<Record>, Error>;
+  fn add_record(&mut self, record: &Record) -> Result<(), Error>;
   fn sub_record(&mut self, record: &str) -> Result<(), Error>;
-  fn execute(&mut self, query: String) -> Result<(), Error>;
+  /// Replaces the checksum of a motion which has already been applied.
+  fn set_checksum(&mut self, record: &Record) -> Result<(), Error>;
+  /// Gives the record of a motion, repeatable or not, a new name. Returns
+  /// whether there was a record to rename.
+  fn rename_record(&mut self, old_name: &str, new_name: &str) -> Result<bool, Error>;
+  /// Gets the records of every repeatable motion which has been executed.
+  fn get_repeatable_records(&self) -> Result<Vec<Record>, Error>;
+  /// Records that a repeatable motion was executed, replacing any record of
+  /// it being executed before.
+  fn set_repeatable_record(&mut self, record: &Record) -> Result<(), Error>;
+  fn execute(&mut self, query: String, options: &ExecuteOptions) -> Result<(), Error>;
+  /// Starts a transaction which everything executed from now on will be a
+  /// part of, even if the queries try to manage transactions themselves.
+  fn begin(&mut self) -> Result<(), Error>;
+  /// Keeps everything done since `begin`.
+  fn commit(&mut self) -> Result<(), Error>;
+  /// Throws away everything done since `begin`.
+  fn rollback(&mut self) -> Result<(), Error>;
+  /// Describes the schema of the database in a normalized and deterministic
+  /// way, so that two databases with the same schema give the same text.
+  fn dump_schema(&self) -> Result<String, Error>;
+  /// Finds the names of all the tenants in the database.
+  fn get_tenants(&self, _tenants: &Tenants) -> Result<Vec<String>, Error> {
+    Err(error!("This driver does not support tenants."))
+  }
+  /// Scopes the records the driver keeps from now on to a namespace, so that
+  /// separate sets of motions can share a database.
+  fn set_namespace(&mut self, _namespace: &str) -> Result<(), Error> {
+    Err(error!("This driver does not support namespaces."))
+  }
+  /// Gets the records, repeatable or not, which were kept before we had a
+  /// namespace and so belong to none. Without a namespace there are none.
+  fn get_records_without_namespace(&self) -> Result<Vec<Record>, Error> {
+    Ok(vec![])
+  }
+  /// Moves the records of some motions which belong to no namespace into ours.
+  fn adopt_records(&mut self, _names: &[String]) -> Result<(), Error> {
+    Err(error!("This driver does not support namespaces."))
+  }
+  /// Scopes everything the driver does from now on, including records, to a
+  /// single tenant.
+  fn set_tenant(&mut self, _tenant: &str) -> Result<(), Error> {
+    Err(error!("This driver does not support tenants."))
+  }
 }
 
 pub fn get(driver_name: Option<&str>, conn_str: &str) -> Result<Box<Driver>, Error> {
@@ -23,10 +93,7 @@ pub fn get(driver_name: Option<&str>, conn_str: &str) -> Result<Box<Driver>, Err
 fn get_by_name(driver_name: &str, conn_str: &str) -> Result<Box<Driver>, Error> {
   match driver_name {
     #[cfg(feature = "driver-test")]
-    "test" => Ok(Box::new(test::TestDriver {
-      records: if conn_str == "" { vec![] } else { conn_str.split(',').map(String::from).collect() },
-      executions: vec![],
-    })),
+    "test" => Ok(Box::new(try!(test::TestDriver::connect(conn_str)))),
 
     #[cfg(feature = "driver-postgres")]
     "postgres" => Ok(Box::new(try!(postgres::PostgresDriver::connect(conn_str)))),
@@ -1,18 +1,85 @@
 extern crate postgres;
 
+use std::collections::BTreeMap;
 use regex::Regex;
-use error::Error;
-use super::Driver;
+use error::{Error, QueryError};
+use super::{Driver, ExecuteOptions, Record, Tenants};
 use self::postgres::{Connection, SslMode};
+use self::postgres::error::{Error as PostgresError, ErrorPosition};
 
 const CREATE_SCHEMA_QUERY: &'static str = "create schema if not exists accelerate";
 const CREATE_TABLE_QUERY: &'static str = "create table if not exists accelerate.record (name text not null)";
-const GET_RECORDS_QUERY: &'static str = "select name from accelerate.record order by name";
-const ADD_RECORD_QUERY: &'static str = "insert into accelerate.record (name) values ($1)";
-const SUB_RECORD_QUERY: &'static str = "delete from accelerate.record where name = $1";
+const ADD_TENANT_COLUMN_QUERY: &'static str = "alter table accelerate.record add column if not exists tenant text not null default ''";
+const ADD_REPEATABLE_COLUMNS_QUERY: &'static str = "alter table accelerate.record add column if not exists checksum text, add column if not exists repeatable boolean not null default false";
+const ADD_NAMESPACE_COLUMN_QUERY: &'static str = "alter table accelerate.record add column if not exists namespace text not null default ''";
+// There is no `order by` here. Records are put in the order of our motions by
+// `diff_motions`, which is the only order they are compared in.
+const GET_RECORDS_QUERY: &'static str = "select name, checksum from accelerate.record where tenant = $1 and namespace = $2 and not repeatable";
+const ADD_RECORD_QUERY: &'static str = "insert into accelerate.record (name, tenant, namespace, checksum) values ($1, $2, $3, $4)";
+const SUB_RECORD_QUERY: &'static str = "delete from accelerate.record where name = $1 and tenant = $2 and namespace = $3 and not repeatable";
+const SET_CHECKSUM_QUERY: &'static str = "update accelerate.record set checksum = $4 where name = $1 and tenant = $2 and namespace = $3 and not repeatable";
+const RENAME_RECORD_QUERY: &'static str = "update accelerate.record set name = $2 where name = $1 and tenant = $3 and namespace = $4";
+const GET_REPEATABLE_RECORDS_QUERY: &'static str = "select name, checksum from accelerate.record where tenant = $1 and namespace = $2 and repeatable";
+const CLEAR_REPEATABLE_RECORD_QUERY: &'static str = "delete from accelerate.record where name = $1 and tenant = $2 and namespace = $3 and repeatable";
+const SET_REPEATABLE_RECORD_QUERY: &'static str = "insert into accelerate.record (name, tenant, namespace, checksum, repeatable) values ($1, $2, $3, $4, true)";
+const GET_RECORDS_WITHOUT_NAMESPACE_QUERY: &'static str = "select name, checksum from accelerate.record where tenant = $1 and namespace = ''";
+const ADOPT_RECORD_QUERY: &'static str = "update accelerate.record set namespace = $3 where name = $1 and tenant = $2 and namespace = ''";
+const GET_TENANTS_QUERY: &'static str = "select nspname from pg_namespace where nspname like $1 order by 1";
+
+// The schemas which belong to the user and not to Postgres or Accelerate.
+macro_rules! user_schemas {
+  () => { "n.nspname not in ('pg_catalog', 'information_schema', 'accelerate') and n.nspname not like 'pg\\_%'" }
+}
+
+const SCHEMAS_QUERY: &'static str = concat!(
+  "select n.nspname from pg_namespace n where ", user_schemas!(), " order by 1"
+);
+const RELATIONS_QUERY: &'static str = concat!(
+  "select n.nspname, c.relname, ",
+  "case c.relkind when 'v' then 'view' when 'm' then 'materialized view' when 'f' then 'foreign table' when 'S' then 'sequence' else 'table' end, ",
+  "case when c.relkind in ('v', 'm') then pg_get_viewdef(c.oid) end ",
+  "from pg_class c join pg_namespace n on n.oid = c.relnamespace ",
+  "where c.relkind in ('r', 'p', 'v', 'm', 'f', 'S') and ", user_schemas!(), " order by 1, 2"
+);
+const COLUMNS_QUERY: &'static str = concat!(
+  "select n.nspname, c.relname, a.attname, format_type(a.atttypid, a.atttypmod), a.attnotnull, pg_get_expr(d.adbin, d.adrelid) ",
+  "from pg_attribute a join pg_class c on c.oid = a.attrelid join pg_namespace n on n.oid = c.relnamespace ",
+  "left join pg_attrdef d on d.adrelid = a.attrelid and d.adnum = a.attnum ",
+  "where a.attnum > 0 and not a.attisdropped and c.relkind in ('r', 'p', 'v', 'm', 'f') and ", user_schemas!(), " order by 1, 2, a.attnum"
+);
+const CONSTRAINTS_QUERY: &'static str = concat!(
+  "select n.nspname, c.relname, 'constraint', con.conname, pg_get_constraintdef(con.oid) ",
+  "from pg_constraint con join pg_class c on c.oid = con.conrelid join pg_namespace n on n.oid = c.relnamespace ",
+  "where ", user_schemas!(), " order by 1, 2, 4"
+);
+const INDEXES_QUERY: &'static str = concat!(
+  "select n.nspname, c.relname, 'index', i.relname, pg_get_indexdef(i.oid) ",
+  "from pg_index x join pg_class c on c.oid = x.indrelid join pg_class i on i.oid = x.indexrelid join pg_namespace n on n.oid = c.relnamespace ",
+  "where ", user_schemas!(), " order by 1, 2, 4"
+);
+const TRIGGERS_QUERY: &'static str = concat!(
+  "select n.nspname, c.relname, 'trigger', t.tgname, pg_get_triggerdef(t.oid) ",
+  "from pg_trigger t join pg_class c on c.oid = t.tgrelid join pg_namespace n on n.oid = c.relnamespace ",
+  "where not t.tgisinternal and ", user_schemas!(), " order by 1, 2, 4"
+);
+const TYPES_QUERY: &'static str = concat!(
+  "select n.nspname, t.typname, string_agg(quote_literal(e.enumlabel), ', ' order by e.enumsortorder) ",
+  "from pg_type t join pg_enum e on e.enumtypid = t.oid join pg_namespace n on n.oid = t.typnamespace ",
+  "where ", user_schemas!(), " group by 1, 2 order by 1, 2"
+);
+const FUNCTIONS_QUERY: &'static str = concat!(
+  "select n.nspname, p.proname, pg_get_function_identity_arguments(p.oid), pg_get_function_result(p.oid), p.prosrc ",
+  "from pg_proc p join pg_namespace n on n.oid = p.pronamespace ",
+  // Procedures have no result, and functions from extensions aren’t ours.
+  "where p.prokind = 'f' and not exists (select 1 from pg_depend d where d.classid = 'pg_proc'::regclass and d.objid = p.oid and d.deptype = 'e') ",
+  "and ", user_schemas!(), " order by 1, 2, 3"
+);
 
 pub struct PostgresDriver {
   connection: Connection,
+  tenant: String,
+  namespace: String,
+  transaction: bool,
 }
 
 impl PostgresDriver {
@@ -21,9 +88,15 @@ impl PostgresDriver {
 
     try!(connection.execute(CREATE_SCHEMA_QUERY, &[]));
     try!(connection.execute(CREATE_TABLE_QUERY, &[]));
+    try!(connection.execute(ADD_TENANT_COLUMN_QUERY, &[]));
+    try!(connection.execute(ADD_REPEATABLE_COLUMNS_QUERY, &[]));
+    try!(connection.execute(ADD_NAMESPACE_COLUMN_QUERY, &[]));
 
     Ok(PostgresDriver {
       connection: connection,
+      tenant: String::new(),
+      namespace: String::new(),
+      transaction: false,
     })
   }
 
@@ -34,12 +107,15 @@ impl PostgresDriver {
 }
 
 impl Driver for PostgresDriver {
-  fn get_records(&self) -> Result<Vec<String>, Error> {
-    Ok(try!(self.connection.query(GET_RECORDS_QUERY, &[])).iter().map(|row| row.get(0)).collect())
+  fn get_records(&self) -> Result<Vec<Record>, Error> {
+    Ok(try!(self.connection.query(GET_RECORDS_QUERY, &[&self.tenant, &self.namespace])).iter().map(|row| Record {
+      name: row.get(0),
+      checksum: row.get(1),
+    }).collect())
   }
 
-  fn add_record(&mut self, record: &str) -> Result<(), Error> {
-    let rows_updated = try!(self.connection.execute(ADD_RECORD_QUERY, &[&record]));
+  fn add_record(&mut self, record: &Record) -> Result<(), Error> {
+    let rows_updated = try!(self.connection.execute(ADD_RECORD_QUERY, &[&record.name, &self.tenant, &self.namespace, &record.checksum]));
     if rows_updated != 1 {
       Err(error!("The number of rows added to the record table was {}, only 1 should have been added.", rows_updated))
     } else {
@@ -48,7 +124,7 @@ impl Driver for PostgresDriver {
   }
 
   fn sub_record(&mut self, record: &str) -> Result<(), Error> {
-    let rows_updated = try!(self.connection.execute(SUB_RECORD_QUERY, &[&record]));
+    let rows_updated = try!(self.connection.execute(SUB_RECORD_QUERY, &[&record, &self.tenant, &self.namespace]));
     if rows_updated != 1 {
       Err(error!("The number of rows removed was {}, only 1 should have been removed.", rows_updated))
     } else {
@@ -56,8 +132,276 @@ impl Driver for PostgresDriver {
     }
   }
 
-  fn execute(&mut self, query: String) -> Result<(), Error> {
-    try!(self.connection.batch_execute(&query));
+  fn set_checksum(&mut self, record: &Record) -> Result<(), Error> {
+    let rows_updated = try!(self.connection.execute(SET_CHECKSUM_QUERY, &[&record.name, &self.tenant, &self.namespace, &record.checksum]));
+    if rows_updated != 1 {
+      Err(error!("The number of rows updated was {}, only 1 should have been updated.", rows_updated))
+    } else {
+      Ok(())
+    }
+  }
+
+  fn rename_record(&mut self, old_name: &str, new_name: &str) -> Result<bool, Error> {
+    let rows_updated = try!(self.connection.execute(RENAME_RECORD_QUERY, &[&old_name, &new_name, &self.tenant, &self.namespace]));
+    Ok(rows_updated > 0)
+  }
+
+  fn get_repeatable_records(&self) -> Result<Vec<Record>, Error> {
+    Ok(try!(self.connection.query(GET_REPEATABLE_RECORDS_QUERY, &[&self.tenant, &self.namespace])).iter().map(|row| Record {
+      name: row.get(0),
+      checksum: row.get(1),
+    }).collect())
+  }
+
+  fn set_repeatable_record(&mut self, record: &Record) -> Result<(), Error> {
+    try!(self.connection.execute(CLEAR_REPEATABLE_RECORD_QUERY, &[&record.name, &self.tenant, &self.namespace]));
+    try!(self.connection.execute(SET_REPEATABLE_RECORD_QUERY, &[&record.name, &self.tenant, &self.namespace, &record.checksum]));
+    Ok(())
+  }
+
+  fn execute(&mut self, query: String, options: &ExecuteOptions) -> Result<(), Error> {
+    // Motions often manage their own transactions. If we are in a transaction
+    // of our own, those would commit it, so we blank them out.
+    let query = if self.transaction { blank_transaction_control(&query) } else { query };
+    // If we were asked for a transaction and are not already in one, start it.
+    let transaction = options.transaction && !self.transaction;
+    if transaction { try!(self.connection.batch_execute("begin")); }
+    if let Some(timeout) = options.timeout {
+      let milliseconds = timeout.as_secs() * 1000 + (timeout.subsec_nanos() / 1000000) as u64;
+      try!(self.connection.batch_execute(&format!("set statement_timeout = {}", milliseconds)));
+    }
+    let result = self.connection.batch_execute(&query);
+    // Clean up after ourselves whether or not the query worked. If the query
+    // failed inside a transaction of the caller’s, nothing more can be done
+    // until it is rolled back, which will also undo our timeout.
+    if transaction { try!(self.connection.batch_execute(if result.is_ok() { "commit" } else { "rollback" })); }
+    if options.timeout.is_some() && (result.is_ok() || !self.transaction) { try!(self.connection.batch_execute("reset statement_timeout")); }
+    // Blanking transaction statements is only our best guess, so make sure the
+    // query really left our transaction open. Releasing the savepoint made in
+    // `begin` fails once the transaction it belongs to is gone.
+    if self.transaction && result.is_ok() && self.connection.batch_execute("release savepoint accelerate; savepoint accelerate").is_err() {
+      return Err(error!("The query ended the transaction it was executed in, so its changes could not be rolled back. Remove any statement which ends a transaction, like `commit` or `end`, from it."));
+    }
+    match result {
+      Ok(()) => Ok(()),
+      Err(error) => {
+        // If Postgres told us where in our query the error happened, pass that
+        // along so the user can find it.
+        let position = match error {
+          PostgresError::Db(ref db_error) => match db_error.position {
+            Some(ErrorPosition::Normal(position)) => Some(position as usize),
+            _ => None,
+          },
+          _ => None,
+        };
+        match position {
+          Some(position) => Err(Box::new(QueryError { position: position, error: Box::new(error) })),
+          None => Err(Box::new(error)),
+        }
+      },
+    }
+  }
+
+  fn begin(&mut self) -> Result<(), Error> {
+    try!(self.connection.batch_execute("begin; savepoint accelerate"));
+    self.transaction = true;
+    Ok(())
+  }
+
+  fn commit(&mut self) -> Result<(), Error> {
+    self.transaction = false;
+    try!(self.connection.batch_execute("commit"));
+    Ok(())
+  }
+
+  fn rollback(&mut self) -> Result<(), Error> {
+    self.transaction = false;
+    try!(self.connection.batch_execute("rollback"));
+    Ok(())
+  }
+
+  fn dump_schema(&self) -> Result<String, Error> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for row in try!(self.connection.query(SCHEMAS_QUERY, &[])).iter() {
+      lines.push(format!("schema {}", row.get::<_, String>(0)));
+    }
+
+    for row in try!(self.connection.query(TYPES_QUERY, &[])).iter() {
+      lines.push(format!("type {}.{} enum ({})", row.get::<_, String>(0), row.get::<_, String>(1), row.get::<_, String>(2)));
+    }
+
+    // Collect everything which belongs to a relation so we can list it under
+    // that relation. All of our queries are ordered, so the order within a
+    // relation is deterministic.
+    let mut members: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+
+    for row in try!(self.connection.query(COLUMNS_QUERY, &[])).iter() {
+      let mut line = format!("  column {} {}", row.get::<_, String>(2), row.get::<_, String>(3));
+      if row.get::<_, bool>(4) { line.push_str(" not null"); }
+      if let Some(default) = row.get::<_, Option<String>>(5) { line.push_str(&format!(" default {}", default)); }
+      members.entry((row.get(0), row.get(1))).or_insert_with(Vec::new).push(line);
+    }
+
+    for query in &[CONSTRAINTS_QUERY, INDEXES_QUERY, TRIGGERS_QUERY] {
+      for row in try!(self.connection.query(query, &[])).iter() {
+        let line = format!("  {} {} {}", row.get::<_, String>(2), row.get::<_, String>(3), row.get::<_, String>(4));
+        members.entry((row.get(0), row.get(1))).or_insert_with(Vec::new).push(line);
+      }
+    }
+
+    for row in try!(self.connection.query(RELATIONS_QUERY, &[])).iter() {
+      let schema: String = row.get(0);
+      let name: String = row.get(1);
+      lines.push(format!("{} {}.{}", row.get::<_, String>(2), schema, name));
+      if let Some(relation_lines) = members.remove(&(schema, name)) { lines.extend(relation_lines); }
+      // Views get their definition collapsed on to a single line.
+      if let Some(definition) = row.get::<_, Option<String>>(3) {
+        lines.push(format!("  as {}", normalize_whitespace(&definition)));
+      }
+    }
+
+    for row in try!(self.connection.query(FUNCTIONS_QUERY, &[])).iter() {
+      lines.push(format!("function {}.{}({}) returns {}", row.get::<_, String>(0), row.get::<_, String>(1), row.get::<_, String>(2), row.get::<_, String>(3)));
+      lines.push(format!("  as {}", normalize_whitespace(&row.get::<_, String>(4))));
+    }
+
+    Ok(lines.iter().map(|line| format!("{}\n", line)).collect())
+  }
+
+  fn get_tenants(&self, tenants: &Tenants) -> Result<Vec<String>, Error> {
+    let rows = match *tenants {
+      Tenants::Pattern(ref pattern) => try!(self.connection.query(GET_TENANTS_QUERY, &[pattern])),
+      Tenants::Query(ref query) => try!(self.connection.query(query, &[])),
+    };
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+  }
+
+  fn set_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+    self.namespace = namespace.to_string();
+    Ok(())
+  }
+
+  fn get_records_without_namespace(&self) -> Result<Vec<Record>, Error> {
+    if self.namespace.is_empty() { return Ok(vec![]); }
+    Ok(try!(self.connection.query(GET_RECORDS_WITHOUT_NAMESPACE_QUERY, &[&self.tenant])).iter().map(|row| Record {
+      name: row.get(0),
+      checksum: row.get(1),
+    }).collect())
+  }
+
+  fn adopt_records(&mut self, names: &[String]) -> Result<(), Error> {
+    for name in names.iter() {
+      try!(self.connection.execute(ADOPT_RECORD_QUERY, &[name, &self.tenant, &self.namespace]));
+    }
+    Ok(())
+  }
+
+  fn set_tenant(&mut self, tenant: &str) -> Result<(), Error> {
+    // Every tenant is a schema, so point our search path at it, keeping
+    // `public` for the extensions and functions tenants share. Quoting the
+    // identifier ourselves is the only option as `set` takes no parameters.
+    try!(self.connection.batch_execute(&format!("set search_path to \"{}\", public", tenant.replace("\"", "\"\""))));
+    self.tenant = tenant.to_string();
     Ok(())
   }
 }
+
+/// Replaces every statement which begins, commits or rolls back a transaction
+/// with spaces. We keep the length the same so that error positions still
+/// point to the right place. Only whole statements are blanked, so the `end`
+/// which closes a block is safe, and quoted text is skipped entirely as a
+/// procedure may commit inside of its own body.
+fn blank_transaction_control(query: &str) -> String {
+  let re = Regex::new(r"(?i)^\s*(begin|start\s+transaction|commit|rollback|abort|end)(\s+(work|transaction))?\s*;").unwrap();
+  let spans = quoted_spans(query);
+  let mut blanked = String::new();
+  let mut last = 0;
+  // Statements start at the beginning of the query and after every semicolon
+  // which isn’t quoted.
+  let starts = Some(0).into_iter().chain(query.char_indices().filter(|&(index, c)| {
+    c == ';' && !spans.iter().any(|&(start, end)| index >= start && index < end)
+  }).map(|(index, _)| index + 1));
+  for start in starts {
+    if start < last { continue; }
+    if let Some((_, end)) = re.find(&query[start..]) {
+      let end = start + end;
+      if spans.iter().any(|&(span_start, span_end)| start < span_end && end > span_start) { continue; }
+      blanked.push_str(&query[last..start]);
+      blanked.extend(query[start..end].chars().map(|c| if c == '\n' { c } else { ' ' }));
+      last = end;
+    }
+  }
+  blanked.push_str(&query[last..]);
+  blanked
+}
+
+/// Finds where every quoted part of a query starts and ends. That is string
+/// literals, dollar quoted bodies like `$body$ … $body$` and comments.
+fn quoted_spans(query: &str) -> Vec<(usize, usize)> {
+  let open_re = Regex::new(r"--|/\*|'|\$([A-Za-z_][A-Za-z0-9_]*)?\$").unwrap();
+  let mut spans = Vec::new();
+  let mut position = 0;
+  while let Some((start, end)) = open_re.find(&query[position..]) {
+    let (start, end) = (position + start, position + end);
+    let close = match &query[start..end] {
+      "--" => "\n",
+      "/*" => "*/",
+      tag => tag,
+    };
+    // Anything which is never closed goes on to the end of the query.
+    let span_end = query[end..].find(close).map_or(query.len(), |index| end + index + close.len());
+    spans.push((start, span_end));
+    position = span_end;
+  }
+  spans
+}
+
+fn normalize_whitespace(text: &str) -> String {
+  text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::blank_transaction_control;
+
+  #[test]
+  fn test_blank_transaction_control() {
+    assert_eq!(
+      blank_transaction_control("begin;\n\ncreate table foo ();\n\ncommit;\n"),
+      "      \n\ncreate table foo ();\n\n       \n"
+    );
+    assert_eq!(
+      blank_transaction_control("START TRANSACTION;\nselect 1;\n  commit work ;"),
+      "                  \nselect 1;\n               "
+    );
+  }
+
+  #[test]
+  fn test_blank_transaction_control_inline() {
+    assert_eq!(
+      blank_transaction_control("create table t (); commit; select 1;begin work;"),
+      "create table t ();         select 1;           "
+    );
+    assert_eq!(
+      blank_transaction_control("create table t ();\nEND;\nend transaction;\n  end work ;\nselect case when true then 1 end;"),
+      "create table t ();\n    \n                \n            \nselect case when true then 1 end;"
+    );
+  }
+
+  #[test]
+  fn test_blank_transaction_control_quoted() {
+    let query = "create function foo() returns void as 'begin perform 1; end;' language plpgsql;\n-- a comment; commit;\nselect ';commit;';";
+    assert_eq!(blank_transaction_control(query), query);
+  }
+
+  #[test]
+  fn test_blank_transaction_control_function() {
+    let query = "create function foo() returns void as $$\nbegin\n  perform 1;\nend;\n$$ language plpgsql;";
+    assert_eq!(blank_transaction_control(query), query);
+    assert_eq!(
+      blank_transaction_control("create procedure foo() as $body$\ncommit;\nend;\n$body$ language plpgsql;\ncommit;"),
+      "create procedure foo() as $body$\ncommit;\nend;\n$body$ language plpgsql;\n       "
+    );
+  }
+}
@@ -1,24 +1,82 @@
-use error::Error;
-use super::Driver;
+use std::mem;
+use std::collections::BTreeMap;
+use regex::{self, Regex};
+use error::{Error, QueryError};
+use super::{Driver, ExecuteOptions, Record, Tenants};
 
-#[derive(Debug)]
+#[derive(Default, Debug)]
 pub struct TestDriver {
-  pub records: Vec<String>,
+  pub records: Vec<Record>,
+  pub repeatable_records: Vec<Record>,
   pub executions: Vec<String>,
+  pub transaction: Option<usize>,
+  /// The tenant our records belong to, empty if they belong to no tenant.
+  pub tenant: String,
+  /// The records and repeatable records of every tenant we aren’t scoped to,
+  /// including those of no tenant under the empty name once we are.
+  pub tenants: BTreeMap<String, (Vec<Record>, Vec<Record>)>,
+  /// Once we have a namespace, the records of no tenant which belong to no
+  /// namespace.
+  pub records_without_namespace: Vec<Record>,
+}
+
+impl TestDriver {
+  /// Creates a test driver from a connection string listing the records it
+  /// starts with, like `123456-foo,234567-bar@0123456789abcdef;views@…`.
+  /// Records may have a checksum after an `@`. Repeatable records come after
+  /// the semicolon and must have one. Records like `billing/123456-foo`
+  /// belong to a namespace, and records like `acme:123456-foo` belong to a
+  /// tenant. Tenants without any records can be listed after a second
+  /// semicolon, like `;;acme,globex`.
+  pub fn connect(conn_str: &str) -> Result<Self, Error> {
+    let mut parts = conn_str.splitn(3, ';');
+    let (records, tenant_records): (Vec<Record>, Vec<Record>) = parse_records(parts.next().unwrap_or("")).into_iter().partition(|record| !record.name.contains(':'));
+    let repeatable_records = parse_records(parts.next().unwrap_or(""));
+    if let Some(record) = repeatable_records.iter().find(|record| record.checksum.is_none()) {
+      return Err(error!("Repeatable record '{}' must have a checksum like `name@checksum`.", record.name));
+    }
+    let mut tenants = BTreeMap::new();
+    for tenant in parts.next().unwrap_or("").split(',').filter(|tenant| !tenant.is_empty()) {
+      tenants.insert(tenant.to_string(), (vec![], vec![]));
+    }
+    for record in tenant_records {
+      let mut name_parts = record.name.splitn(2, ':');
+      let tenant = name_parts.next().unwrap().to_string();
+      let name = name_parts.next().unwrap().to_string();
+      tenants.entry(tenant).or_insert_with(|| (vec![], vec![])).0.push(Record { name: name, checksum: record.checksum });
+    }
+    Ok(TestDriver {
+      records: records,
+      repeatable_records: repeatable_records,
+      tenants: tenants,
+      ..TestDriver::default()
+    })
+  }
+}
+
+fn parse_records(records: &str) -> Vec<Record> {
+  if records == "" { return vec![]; }
+  records.split(',').map(|record| {
+    let mut parts = record.splitn(2, '@');
+    Record {
+      name: parts.next().unwrap().to_string(),
+      checksum: parts.next().map(String::from),
+    }
+  }).collect()
 }
 
 impl Driver for TestDriver {
-  fn get_records(&self) -> Result<Vec<String>, Error> {
+  fn get_records(&self) -> Result<Vec<Record>, Error> {
     Ok(self.records.clone())
   }
 
-  fn add_record(&mut self, record: &str) -> Result<(), Error> {
-    self.records.push(record.to_string());
+  fn add_record(&mut self, record: &Record) -> Result<(), Error> {
+    self.records.push(record.clone());
     Ok(())
   }
 
   fn sub_record(&mut self, record: &str) -> Result<(), Error> {
-    if let Some(index) = self.records.iter().position(|r| r == record) {
+    if let Some(index) = self.records.iter().position(|r| r.name == record) {
       self.records.remove(index);
       Ok(())
     } else {
@@ -26,8 +84,125 @@ impl Driver for TestDriver {
     }
   }
 
-  fn execute(&mut self, query: String) -> Result<(), Error> {
+  fn set_checksum(&mut self, record: &Record) -> Result<(), Error> {
+    match self.records.iter_mut().find(|r| r.name == record.name) {
+      Some(r) => {
+        r.checksum = record.checksum.clone();
+        Ok(())
+      },
+      None => Err(error!("Record '{}' could not be updated because it was never applied.", record.name)),
+    }
+  }
+
+  fn rename_record(&mut self, old_name: &str, new_name: &str) -> Result<bool, Error> {
+    let mut renamed = false;
+    for record in self.records.iter_mut().chain(self.repeatable_records.iter_mut()).filter(|record| record.name == old_name) {
+      record.name = new_name.to_string();
+      renamed = true;
+    }
+    Ok(renamed)
+  }
+
+  fn get_repeatable_records(&self) -> Result<Vec<Record>, Error> {
+    Ok(self.repeatable_records.clone())
+  }
+
+  fn set_repeatable_record(&mut self, record: &Record) -> Result<(), Error> {
+    self.repeatable_records.retain(|r| r.name != record.name);
+    self.repeatable_records.push(record.clone());
+    Ok(())
+  }
+
+  fn execute(&mut self, query: String, _options: &ExecuteOptions) -> Result<(), Error> {
+    // Fail on any query which says so, pointing at where it did. This lets us
+    // test how errors from a driver are reported.
+    if let Some(index) = query.find("fail") {
+      return Err(Box::new(QueryError {
+        position: query[..index].chars().count() + 1,
+        error: error!("Query asked to fail."),
+      }));
+    }
     self.executions.push(query);
     Ok(())
   }
+
+  fn begin(&mut self) -> Result<(), Error> {
+    if self.transaction.is_some() { return Err(error!("A transaction has already begun.")); }
+    self.transaction = Some(self.executions.len());
+    Ok(())
+  }
+
+  fn commit(&mut self) -> Result<(), Error> {
+    try!(self.transaction.take().ok_or(error!("There is no transaction to commit.")));
+    Ok(())
+  }
+
+  fn rollback(&mut self) -> Result<(), Error> {
+    let start = try!(self.transaction.take().ok_or(error!("There is no transaction to roll back.")));
+    self.executions.truncate(start);
+    Ok(())
+  }
+
+  fn set_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+    // Keep only the records in our namespace, without their prefix.
+    let prefix = format!("{}/", namespace);
+    self.records_without_namespace = self.records.iter().chain(self.repeatable_records.iter()).filter(|record| !record.name.contains('/')).cloned().collect();
+    let scope = |records: &mut Vec<Record>| {
+      records.retain(|record| record.name.starts_with(&prefix));
+      for record in records.iter_mut() { record.name = record.name[prefix.len()..].to_string(); }
+    };
+    scope(&mut self.records);
+    scope(&mut self.repeatable_records);
+    for &mut (ref mut records, ref mut repeatable_records) in self.tenants.values_mut() {
+      scope(records);
+      scope(repeatable_records);
+    }
+    Ok(())
+  }
+
+  fn get_records_without_namespace(&self) -> Result<Vec<Record>, Error> {
+    Ok(if self.tenant.is_empty() { self.records_without_namespace.clone() } else { vec![] })
+  }
+
+  fn adopt_records(&mut self, names: &[String]) -> Result<(), Error> {
+    for name in names.iter() {
+      let index = try!(self.records_without_namespace.iter().position(|record| &record.name == name).ok_or(error!("Record '{}' could not be adopted because it already belongs to a namespace.", name)));
+      self.records.push(self.records_without_namespace.remove(index));
+    }
+    Ok(())
+  }
+
+  fn get_tenants(&self, tenants: &Tenants) -> Result<Vec<String>, Error> {
+    match *tenants {
+      Tenants::Pattern(ref pattern) => {
+        // Turn the `like` pattern into a regular expression.
+        let re = Regex::new(&format!("^{}$", pattern.chars().map(|c| match c {
+          '%' => ".*".to_string(),
+          '_' => ".".to_string(),
+          _ => regex::quote(&c.to_string()),
+        }).collect::<String>())).unwrap();
+        let mut names = self.tenants.keys().chain(Some(&self.tenant)).filter(|tenant| !tenant.is_empty() && re.is_match(tenant)).cloned().collect::<Vec<String>>();
+        names.sort();
+        Ok(names)
+      },
+      Tenants::Query(_) => Err(error!("This driver does not support tenant queries.")),
+    }
+  }
+
+  fn set_tenant(&mut self, tenant: &str) -> Result<(), Error> {
+    if tenant == self.tenant { return Ok(()); }
+    let (records, repeatable_records) = try!(self.tenants.remove(tenant).ok_or(error!("Tenant '{}' does not exist.", tenant)));
+    // Put away the records of the tenant we had before.
+    let records = mem::replace(&mut self.records, records);
+    let repeatable_records = mem::replace(&mut self.repeatable_records, repeatable_records);
+    self.tenants.insert(mem::replace(&mut self.tenant, tenant.to_string()), (records, repeatable_records));
+    Ok(())
+  }
+
+  fn dump_schema(&self) -> Result<String, Error> {
+    // The test driver has no real schema, so our records will have to do.
+    let mut records = self.records.iter().map(|record| record.name.clone()).collect::<Vec<String>>();
+    records.sort();
+    Ok(records.iter().map(|record| format!("record {}\n", record)).collect())
+  }
 }
@@ -24,3 +24,24 @@ impl error::Error for CustomError {
     &self.0
   }
 }
+
+/// An error a driver hit at a specific position in the query it was
+/// executing. The position is the 1-based character offset into the query,
+/// which is how Postgres reports it.
+#[derive(Debug)]
+pub struct QueryError {
+  pub position: usize,
+  pub error: Error,
+}
+
+impl fmt::Display for QueryError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.error)
+  }
+}
+
+impl error::Error for QueryError {
+  fn description(&self) -> &str {
+    self.error.description()
+  }
+}
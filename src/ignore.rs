@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::fs::File;
+use regex;
+use regex::Regex;
+use error::Error;
+
+/// The name of the files which list paths motion discovery should skip.
+pub const FILE_NAME: &'static str = ".accelerateignore";
+
+/// The patterns from every ignore file between the motions directory and the
+/// directory we are currently looking in. Patterns use the same syntax as a
+/// `.gitignore` and apply to the directory their file is in and everything
+/// below it.
+#[derive(Clone, Default, Debug)]
+pub struct Ignore {
+  patterns: Vec<Pattern>,
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+  /// The directory of the ignore file this pattern came from.
+  base: PathBuf,
+  regex: Regex,
+  /// Whether this pattern un-ignores paths instead, like `!keep.sql`.
+  negated: bool,
+  /// Whether this pattern only matches directories, like `drafts/`.
+  directory_only: bool,
+}
+
+impl Ignore {
+  /// Adds the patterns from the ignore file in a directory, if it has one.
+  pub fn read(&self, dir: &Path) -> Result<Ignore, Error> {
+    let path = dir.join(FILE_NAME);
+    let mut text = String::new();
+    match File::open(&path) {
+      Ok(mut file) => { try!(file.read_to_string(&mut text)); },
+      Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(self.clone()),
+      Err(error) => return Err(Box::new(error)),
+    }
+    let mut ignore = self.clone();
+    for (index, line) in text.lines().enumerate() {
+      match Pattern::parse(dir, line) {
+        Ok(Some(pattern)) => ignore.patterns.push(pattern),
+        Ok(None) => (),
+        Err(error) => return Err(error!("Invalid pattern '{}' on line {} of ignore file '{}': {}", line.trim_right(), index + 1, path.display(), error)),
+      }
+    }
+    Ok(ignore)
+  }
+
+  /// Whether a path should be skipped. The last pattern to match a path
+  /// decides, so later patterns can un-ignore what earlier ones ignored.
+  pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for pattern in self.patterns.iter() {
+      if pattern.directory_only && !is_dir { continue; }
+      // Patterns match against the path relative to their ignore file with
+      // forward slashes, whatever the platform.
+      let relative = match path.strip_prefix(&pattern.base) { Ok(relative) => relative, Err(_) => continue };
+      let relative = relative.components().map(|component| component.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/");
+      if pattern.regex.is_match(&relative) { ignored = !pattern.negated; }
+    }
+    ignored
+  }
+}
+
+impl Pattern {
+  /// Parses a line from an ignore file. Blank lines and comments give us
+  /// nothing.
+  fn parse(base: &Path, line: &str) -> Result<Option<Pattern>, Error> {
+    let mut line = line.trim_right();
+    if line.is_empty() || line.starts_with("#") { return Ok(None); }
+    let negated = line.starts_with("!");
+    if negated { line = &line[1..]; }
+    // A backslash lets a pattern start with a `#` or `!`.
+    if line.starts_with("\\#") || line.starts_with("\\!") { line = &line[1..]; }
+    let directory_only = line.ends_with("/");
+    if directory_only { line = line.trim_right_matches('/'); }
+    // Patterns with a slash are relative to the ignore file, anything else
+    // may match a file or directory at any depth.
+    let anchored = line.contains('/');
+    if anchored { line = line.trim_left_matches('/'); }
+    if line.is_empty() { return Ok(None); }
+    let mut source = if anchored { "^".to_owned() } else { "^(?:.*/)?".to_owned() };
+    source.push_str(&glob_to_regex(line));
+    source.push('$');
+    Ok(Some(Pattern {
+      base: base.to_path_buf(),
+      regex: try!(Regex::new(&source)),
+      negated: negated,
+      directory_only: directory_only,
+    }))
+  }
+}
+
+/// Translates a glob like `drafts/**/*.sql` into a regular expression. A `*`
+/// never matches a slash but a `**` does.
+fn glob_to_regex(glob: &str) -> String {
+  let chars = glob.chars().collect::<Vec<char>>();
+  let mut source = String::new();
+  let mut i = 0;
+  while i < chars.len() {
+    match chars[i] {
+      '*' if chars.get(i + 1) == Some(&'*') => {
+        // A `**/` matches any number of directories, including none.
+        if chars.get(i + 2) == Some(&'/') {
+          source.push_str("(?:.*/)?");
+          i += 3;
+        } else {
+          source.push_str(".*");
+          i += 2;
+        }
+        continue;
+      },
+      '*' => source.push_str("[^/]*"),
+      '?' => source.push_str("[^/]"),
+      '[' => {
+        // Copy character classes across, if they are closed.
+        match chars[i..].iter().position(|&c| c == ']') {
+          Some(length) if length > 1 => {
+            source.push('[');
+            for (j, &c) in chars[i + 1..i + length].iter().enumerate() {
+              if j == 0 && c == '!' { source.push('^'); } else if c == '\\' || c == '[' { source.push('\\'); source.push(c); } else { source.push(c); }
+            }
+            source.push(']');
+            i += length + 1;
+            continue;
+          },
+          _ => source.push_str("\\["),
+        }
+      },
+      c => source.push_str(&regex::quote(&c.to_string())),
+    }
+    i += 1;
+  }
+  source
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs::{self, File};
+  use std::io::prelude::*;
+  use std::path::Path;
+  use super::{Ignore, Pattern, FILE_NAME};
+
+  fn ignore(lines: &[&str]) -> Ignore {
+    Ignore { patterns: lines.iter().filter_map(|line| Pattern::parse(Path::new("motions"), line).unwrap()).collect() }
+  }
+
+  #[test]
+  fn test_is_ignored_name() {
+    let ignore = ignore(&["# Backups.", "*.bak", "", "README*"]);
+    assert!(ignore.is_ignored(Path::new("motions/foo.bak"), false));
+    assert!(ignore.is_ignored(Path::new("motions/a/b/foo.bak"), false));
+    assert!(ignore.is_ignored(Path::new("motions/a/README.md"), false));
+    assert!(!ignore.is_ignored(Path::new("motions/foo.bak.sql"), false));
+    assert!(!ignore.is_ignored(Path::new("other/foo.bak"), false));
+  }
+
+  #[test]
+  fn test_is_ignored_anchored() {
+    let ignore = ignore(&["/notes.sql", "scratch/*.sql", "drafts/**/old"]);
+    assert!(ignore.is_ignored(Path::new("motions/notes.sql"), false));
+    assert!(!ignore.is_ignored(Path::new("motions/a/notes.sql"), false));
+    assert!(ignore.is_ignored(Path::new("motions/scratch/foo.sql"), false));
+    assert!(!ignore.is_ignored(Path::new("motions/scratch/a/foo.sql"), false));
+    assert!(ignore.is_ignored(Path::new("motions/drafts/old"), true));
+    assert!(ignore.is_ignored(Path::new("motions/drafts/a/b/old"), true));
+  }
+
+  #[test]
+  fn test_is_ignored_directory() {
+    let ignore = ignore(&["drafts/"]);
+    assert!(ignore.is_ignored(Path::new("motions/drafts"), true));
+    assert!(ignore.is_ignored(Path::new("motions/a/drafts"), true));
+    assert!(!ignore.is_ignored(Path::new("motions/drafts"), false));
+  }
+
+  #[test]
+  fn test_is_ignored_negated() {
+    let ignore = ignore(&["*.sql", "!keep.sql", "\\!bang.sql"]);
+    assert!(ignore.is_ignored(Path::new("motions/foo.sql"), false));
+    assert!(!ignore.is_ignored(Path::new("motions/keep.sql"), false));
+    assert!(ignore.is_ignored(Path::new("motions/!bang.sql"), false));
+  }
+
+  #[test]
+  fn test_is_ignored_class() {
+    let ignore = ignore(&["draft[0-9].sql", "tmp[!a].sql", "?.sql"]);
+    assert!(ignore.is_ignored(Path::new("motions/draft1.sql"), false));
+    assert!(!ignore.is_ignored(Path::new("motions/drafts.sql"), false));
+    assert!(ignore.is_ignored(Path::new("motions/tmpb.sql"), false));
+    assert!(!ignore.is_ignored(Path::new("motions/tmpa.sql"), false));
+    assert!(ignore.is_ignored(Path::new("motions/x.sql"), false));
+  }
+
+  #[test]
+  fn test_parse_invalid() {
+    assert!(Pattern::parse(Path::new("motions"), "[z-a].sql").is_err());
+    assert!(Pattern::parse(Path::new("motions"), "[^].sql").is_err());
+  }
+
+  #[test]
+  fn test_read() {
+    let ignore = Ignore::default().read(Path::new("tests/fixtures/ignore")).unwrap();
+    assert!(ignore.is_ignored(Path::new("tests/fixtures/ignore/drafts"), true));
+    assert!(ignore.is_ignored(Path::new("tests/fixtures/ignore/scratch/stray.sub"), false));
+    assert!(!ignore.is_ignored(Path::new("tests/fixtures/ignore/123456-foo.add"), false));
+  }
+
+  #[test]
+  fn test_read_invalid() {
+    let dir = env::temp_dir().join("accelerate-test-ignore-invalid");
+    fs::create_dir_all(&dir).unwrap();
+    File::create(dir.join(FILE_NAME)).unwrap().write_all(b"*.bak\n[z-a].sql\n").unwrap();
+    let error = Ignore::default().read(&dir).unwrap_err();
+    assert!(format!("{}", error).starts_with(&format!("Invalid pattern '[z-a].sql' on line 2 of ignore file '{}':", dir.join(FILE_NAME).display())));
+  }
+}
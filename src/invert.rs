@@ -0,0 +1,189 @@
+use regex::Regex;
+
+/// A name which may be quoted and may have a schema, like `public."User"`.
+const NAME: &'static str = r#"((?:"[^"]*"|[\w$]+)(?:\s*\.\s*(?:"[^"]*"|[\w$]+))*)"#;
+
+/// Writes the statements which undo some SQL, by undoing each statement in
+/// reverse order. Statements we don’t know how to undo are left in comments
+/// marked with a `TODO` for someone to finish by hand.
+pub fn invert(text: &str) -> String {
+  let mut inverted = String::new();
+  for statement in split_statements(text).iter().rev() {
+    match invert_statement(statement) {
+      Some(inverse) => {
+        inverted.push_str(&inverse);
+        inverted.push('\n');
+      },
+      None => {
+        inverted.push_str("-- TODO: Undo this statement by hand.\n");
+        for line in statement.lines() {
+          inverted.push_str(&format!("-- {}\n", line).replace("-- \n", "--\n"));
+        }
+      },
+    }
+  }
+  inverted
+}
+
+/// Undoes a single statement, if it is one we recognise.
+fn invert_statement(statement: &str) -> Option<String> {
+  let create_re = Regex::new(&format!(r"(?is)^create\s+(table|view|materialized\s+view|schema|sequence|type)\s+(?:if\s+not\s+exists\s+)?{}", NAME)).unwrap();
+  let create_index_re = Regex::new(&format!(r"(?is)^create\s+(?:unique\s+)?index\s+(?:concurrently\s+)?(?:if\s+not\s+exists\s+)?{}\s+on\s+(?:only\s+)?{}", NAME, NAME)).unwrap();
+  let alter_table_re = Regex::new(&format!(r"(?is)^alter\s+table\s+(?:if\s+exists\s+)?(?:only\s+)?{}\s+(.*)$", NAME)).unwrap();
+  let add_column_re = Regex::new(&format!(r"(?is)^add\s+(?:column\s+)?(?:if\s+not\s+exists\s+)?{}\s", NAME)).unwrap();
+
+  if let Some(captures) = create_re.captures(statement) {
+    let kind = captures.at(1).unwrap().split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase();
+    return Some(format!("drop {} {};", kind, captures.at(2).unwrap()));
+  }
+  if let Some(captures) = create_index_re.captures(statement) {
+    // An index is always in the same schema as its table.
+    let (index, table) = (captures.at(1).unwrap(), captures.at(2).unwrap());
+    return match (index.contains('.'), table.rfind('.')) {
+      (false, Some(dot)) => Some(format!("drop index {}.{};", table[..dot].trim(), index)),
+      _ => Some(format!("drop index {};", index)),
+    };
+  }
+  if let Some(captures) = alter_table_re.captures(statement) {
+    // Every action must add a column for us to undo the statement. Constraints
+    // and the like are too varied to undo reliably.
+    let constraint_re = Regex::new(r"(?i)^add\s+(constraint|primary|unique|foreign|check|exclude)\b").unwrap();
+    let mut columns = Vec::new();
+    for action in split_top_level(captures.at(2).unwrap(), ',') {
+      let action = action.trim().to_owned() + " ";
+      if constraint_re.is_match(&action) { return None; }
+      match add_column_re.captures(&action) {
+        Some(action_captures) => columns.push(action_captures.at(1).unwrap().to_string()),
+        None => return None,
+      }
+    }
+    let drops = columns.iter().rev().map(|column| format!("drop column {}", column)).collect::<Vec<String>>();
+    return Some(format!("alter table {} {};", captures.at(1).unwrap(), drops.join(", ")));
+  }
+  None
+}
+
+/// Splits SQL into statements without their comments or the semicolons which
+/// end them. Semicolons in strings, quoted names and dollar quoted bodies
+/// don’t end a statement.
+fn split_statements(text: &str) -> Vec<String> {
+  let chars = text.chars().collect::<Vec<char>>();
+  let mut statements = Vec::new();
+  let mut statement = String::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    let next = chars.get(i + 1).cloned();
+    if c == '-' && next == Some('-') {
+      // Skip a line comment, but keep its newline.
+      while i < chars.len() && chars[i] != '\n' { i += 1; }
+      continue;
+    } else if c == '/' && next == Some('*') {
+      // Skip a block comment, leaving a space in its place.
+      i += 2;
+      while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) { i += 1; }
+      i += 2;
+      statement.push(' ');
+      continue;
+    } else if c == '\'' || c == '"' {
+      // Copy a string or quoted name, a doubled quote is an escaped quote.
+      let end = chars[i + 1..].iter().position(|&other| other == c).map(|position| i + 1 + position).unwrap_or(chars.len() - 1);
+      statement.extend(chars[i..end + 1].iter());
+      i = end + 1;
+      continue;
+    } else if c == '$' {
+      // Copy a dollar quoted body like `$body$ … $body$`.
+      let tag_end = chars[i + 1..].iter().position(|&other| !(other.is_alphanumeric() || other == '_')).map(|position| i + 1 + position);
+      if let Some(tag_end) = tag_end {
+        if chars[tag_end] == '$' {
+          let tag = chars[i..tag_end + 1].iter().cloned().collect::<String>();
+          let rest = chars[tag_end + 1..].iter().cloned().collect::<String>();
+          let body_length = rest.find(&tag).map(|position| rest[..position].chars().count() + tag.chars().count()).unwrap_or(rest.chars().count());
+          let end = tag_end + 1 + body_length;
+          statement.extend(chars[i..end].iter());
+          i = end;
+          continue;
+        }
+      }
+    } else if c == ';' {
+      push_statement(&mut statements, &statement);
+      statement.clear();
+      i += 1;
+      continue;
+    }
+    statement.push(c);
+    i += 1;
+  }
+  push_statement(&mut statements, &statement);
+  statements
+}
+
+fn push_statement(statements: &mut Vec<String>, statement: &str) {
+  let statement = statement.trim();
+  if !statement.is_empty() { statements.push(statement.to_string()); }
+}
+
+/// Splits text at a separator, except where it is inside of parentheses.
+fn split_top_level(text: &str, separator: char) -> Vec<String> {
+  let mut parts = Vec::new();
+  let mut part = String::new();
+  let mut depth = 0;
+  for c in text.chars() {
+    match c {
+      '(' => depth += 1,
+      ')' => depth -= 1,
+      _ if c == separator && depth == 0 => {
+        parts.push(part.clone());
+        part.clear();
+        continue;
+      },
+      _ => (),
+    }
+    part.push(c);
+  }
+  parts.push(part);
+  parts
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{invert, split_statements};
+
+  #[test]
+  fn test_split_statements() {
+    assert_eq!(split_statements("-- description: Users\n\ncreate table a (b text default ';');\n/* ; */ select $x$ ; $x$, \"c;\";\n\n"), vec![
+      "create table a (b text default ';')".to_string(),
+      "select $x$ ; $x$, \"c;\"".to_string(),
+    ]);
+  }
+
+  #[test]
+  fn test_invert() {
+    assert_eq!(invert(concat!(
+      "create table if not exists app.users (id serial primary key, name text);\n",
+      "CREATE UNIQUE INDEX users_name ON app.users (name);\n",
+      "alter table app.users add column email text, add age int default (1 + 2);\n",
+      "create view \"Active Users\" as select 1;\n",
+      "create index on app.users (email);\n",
+    )), concat!(
+      "-- TODO: Undo this statement by hand.\n",
+      "-- create index on app.users (email)\n",
+      "drop view \"Active Users\";\n",
+      "alter table app.users drop column age, drop column email;\n",
+      "drop index app.users_name;\n",
+      "drop table app.users;\n",
+    ));
+  }
+
+  #[test]
+  fn test_invert_unknown() {
+    assert_eq!(invert("create table a ();\ninsert into a\nvalues (1);\nalter table a add constraint b check (true);\n"), concat!(
+      "-- TODO: Undo this statement by hand.\n",
+      "-- alter table a add constraint b check (true)\n",
+      "-- TODO: Undo this statement by hand.\n",
+      "-- insert into a\n",
+      "-- values (1)\n",
+      "drop table a;\n",
+    ));
+  }
+}
@@ -9,6 +9,14 @@ mod error;
 mod motions;
 mod accelerator;
 mod driver;
+mod source;
+mod schema;
+mod checksum;
+mod config;
+mod ignore;
+mod variables;
+mod aliases;
+mod invert;
 
 use std::env;
 use std::path::Path;
@@ -17,10 +25,14 @@ use std::io;
 use std::io::prelude::*;
 use clap::{App, Arg, SubCommand};
 use clap::AppSettings::*;
-use colored::Colorize;
+use colored::{Colorize, ColoredString};
 use error::Error;
-use accelerator::Accelerator;
-use driver::Driver;
+use accelerator::{Accelerator, Status};
+use config::Config;
+use motions::{Motion, FindOptions, Naming};
+use driver::{Driver, Record, Tenants};
+use variables::Variables;
+use aliases::Aliases;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -40,6 +52,46 @@ fn run() -> Result<(), Error> {
     .value_name("PATH")
   );
 
+  let discovery_args = [
+    Arg::with_name("max_depth")
+    .help("How many directories deep to look for motions, can also be set with `max_depth` in the config file")
+    .long("max-depth")
+    .value_name("N"),
+
+    Arg::with_name("symlinks")
+    .help("Whether to follow, skip or error on symbolic links when looking for motions, can also be set with `symlinks` in the config file")
+    .long("symlinks")
+    .value_name("POLICY")
+    .possible_values(&["follow", "skip", "error"]),
+
+    Arg::with_name("order")
+    .help("Whether to sort motion names naturally, with numbers compared by value, or lexically, can also be set with `order` in the config file")
+    .long("order")
+    .value_name("ORDER")
+    .possible_values(&["natural", "lexical"]),
+
+    Arg::with_name("namespace")
+    .help("Keeps these motions and their records apart from those of other namespaces in the same database, can also be set with `namespace` in the config file")
+    .long("namespace")
+    .value_name("NAME")
+  ];
+
+  let tag_args = [
+    Arg::with_name("tag")
+    .help("Only uses motions with this tag, tags come from the directories a motion is in, the parts of its name after a dot and its header")
+    .long("tag")
+    .value_name("TAG")
+    .multiple(true)
+    .number_of_values(1),
+
+    Arg::with_name("exclude_tag")
+    .help("Leaves out motions with this tag")
+    .long("exclude-tag")
+    .value_name("TAG")
+    .multiple(true)
+    .number_of_values(1)
+  ];
+
   let driver_args = [
     Arg::with_name("database")
     .help("The connection string to connecting to your database, can also be set with ACCELERATE_DATABASE")
@@ -55,6 +107,19 @@ fn run() -> Result<(), Error> {
     .value_name("NAME")
   ];
 
+  let tenant_args = [
+    Arg::with_name("tenants")
+    .help("A pattern, using SQL `like` syntax, matching the schema of every tenant to apply motions to, can also be set with ACCELERATE_TENANTS")
+    .long("tenants")
+    .value_name("PATTERN")
+    .conflicts_with("tenants_query"),
+
+    Arg::with_name("tenants_query")
+    .help("A query returning the schema of every tenant to apply motions to")
+    .long("tenants-query")
+    .value_name("QUERY")
+  ];
+
   let auto_confirm_arg = (
     Arg::with_name("auto_confirm")
     .help("Automatically confirm when removing information, this should only be used in automated environments")
@@ -62,7 +127,29 @@ fn run() -> Result<(), Error> {
     .long("yes")
   );
 
-  let matches = (
+  let dump_schema_arg = (
+    Arg::with_name("dump_schema")
+    .help("A file to write a description of the database schema to after the motions have run, can also be set with ACCELERATE_DUMP_SCHEMA")
+    .long("dump-schema")
+    .value_name("FILE")
+  );
+
+  let allow_out_of_order_arg = (
+    Arg::with_name("allow_out_of_order")
+    .help("Applies motions which come before motions that have already been applied, like those from a merged branch, before any others")
+    .long("allow-out-of-order")
+  );
+
+  let var_arg = (
+    Arg::with_name("var")
+    .help("Sets a variable for the `${name}` placeholders in motions which turn them on, overriding any set in the config file")
+    .long("var")
+    .value_name("NAME=VALUE")
+    .multiple(true)
+    .number_of_values(1)
+  );
+
+  let app_matches = (
     App::new("Accelerate")
     .bin_name("accelerate")
     .version(VERSION)
@@ -80,12 +167,80 @@ fn run() -> Result<(), Error> {
       SubCommand::with_name("ls")
       .about("Lists all of your available motions")
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
     )
     .subcommand(
       SubCommand::with_name("status")
       .about("Informs you about the status of all your motions in the database")
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
       .args(&driver_args)
+      .args(&tenant_args)
+    )
+    .subcommand(
+      SubCommand::with_name("accept-checksum")
+      .about("Accepts the changes made to a motion after it was applied by recording its new checksum")
+      .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&driver_args)
+      .args(&tenant_args)
+      .arg(
+        Arg::with_name("motion")
+        .help("The name of the motion whose changes you want to accept")
+        .required(true)
+        .value_name("MOTION")
+      )
+    )
+    .subcommand(
+      SubCommand::with_name("adopt-records")
+      .about("Moves the records of motions applied before they had a namespace into their namespace, so they are not applied again")
+      .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&driver_args)
+      .args(&tenant_args)
+    )
+    .subcommand(
+      SubCommand::with_name("rename")
+      .about("Renames the files of a motion and its records, other databases will be updated the next time Accelerate runs against them")
+      .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&driver_args)
+      .args(&tenant_args)
+      .arg(
+        Arg::with_name("motion")
+        .help("The name of the motion you want to rename")
+        .required(true)
+        .value_name("MOTION")
+      )
+      .arg(
+        Arg::with_name("name")
+        .help("The new name for the motion, without a directory or extension")
+        .required(true)
+        .value_name("NAME")
+      )
+    )
+    .subcommand(
+      SubCommand::with_name("squash")
+      .about("Squashes every motion up to a point into one, archiving the originals, so new databases are quicker to set up")
+      .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&driver_args)
+      .args(&tenant_args)
+      .arg(
+        Arg::with_name("until")
+        .help("The name of the last motion to squash")
+        .long("until")
+        .required(true)
+        .value_name("MOTION")
+      )
+      .arg(
+        Arg::with_name("name")
+        .help("The name of the squashed motion, by default the number of the last motion followed by `-baseline`")
+        .long("name")
+        .value_name("NAME")
+      )
     )
     .subcommand(
       SubCommand::with_name("create")
@@ -93,16 +248,59 @@ fn run() -> Result<(), Error> {
       .arg(&directory_arg)
       .arg(
         Arg::with_name("name")
-        .help("The name (with directory path) of the motion you want to create. A timestamp will automatically be added to the last segment")
+        .help("The name (with directory path) of the motion you want to create. A timestamp or number will automatically be added to the last segment")
         .required(true)
         .value_name("NAME")
       )
+      .arg(
+        Arg::with_name("single")
+        .help("Create a single file with add and sub sections instead of separate add and sub files")
+        .long("single")
+      )
+      .arg(
+        Arg::with_name("template")
+        .help("The name of the template to create the motion from, like `table` for `template.table.add.sql`")
+        .long("template")
+        .value_name("TEMPLATE")
+      )
+      .arg(
+        Arg::with_name("naming")
+        .help("How to prefix the name of the motion, can also be set with `naming` in the config file")
+        .long("naming")
+        .value_name("SCHEME")
+        .possible_values(&["minutes", "seconds", "datetime", "sequential"])
+      )
+      .arg(
+        Arg::with_name("from")
+        .help("An existing add file to copy instead of the template, with a sub file generated to undo it")
+        .long("from")
+        .value_name("FILE")
+      )
+    )
+    .subcommand(
+      SubCommand::with_name("gen-sub")
+      .about("Generates a sub file for a motion from its add file, leaving a TODO for anything which can’t be undone automatically")
+      .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&driver_args)
+      .args(&tenant_args)
+      .arg(
+        Arg::with_name("motion")
+        .help("The name of the motion to generate a sub file for")
+        .required(true)
+        .value_name("MOTION")
+      )
     )
     .subcommand(
       SubCommand::with_name("add")
       .about("Will add `n` motions to the driver")
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
       .args(&driver_args)
+      .args(&tenant_args)
+      .arg(&var_arg)
+      .arg(&dump_schema_arg)
       .arg(
         Arg::with_name("n")
         .help("The number of motions to add to the driver")
@@ -115,7 +313,12 @@ fn run() -> Result<(), Error> {
       .about("Will sub `n` motions in the driver")
       .arg(&auto_confirm_arg)
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
       .args(&driver_args)
+      .args(&tenant_args)
+      .arg(&var_arg)
+      .arg(&dump_schema_arg)
       .arg(
         Arg::with_name("n")
         .help("The number of motions to sub in the driver")
@@ -125,105 +328,598 @@ fn run() -> Result<(), Error> {
     )
     .subcommand(
       SubCommand::with_name("up")
-      .about("Will add all motions that have not yet been applied to the database")
+      .about("Will add all motions that have not yet been applied to the database and then execute any repeatable motions which have changed")
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
       .args(&driver_args)
+      .args(&tenant_args)
+      .arg(&var_arg)
+      .arg(&dump_schema_arg)
+      .arg(&allow_out_of_order_arg)
     )
     .subcommand(
       SubCommand::with_name("down")
       .about("Will sub all motions that have been applied in the database")
       .arg(&auto_confirm_arg)
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
       .args(&driver_args)
+      .args(&tenant_args)
+      .arg(&var_arg)
+      .arg(&dump_schema_arg)
     )
     .subcommand(
       SubCommand::with_name("redo")
       .about("Will sub and then add just the last motion")
       .arg(&auto_confirm_arg)
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
       .args(&driver_args)
+      .args(&tenant_args)
+      .arg(&var_arg)
+      .arg(&dump_schema_arg)
+      .arg(&allow_out_of_order_arg)
     )
     .subcommand(
       SubCommand::with_name("reset")
       .about("Will sub all motions that have been applied in the database and then add all of the motions available")
       .arg(&auto_confirm_arg)
       .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
       .args(&driver_args)
+      .args(&tenant_args)
+      .arg(&var_arg)
+      .arg(&dump_schema_arg)
+      .arg(&allow_out_of_order_arg)
+    )
+    .subcommand(
+      SubCommand::with_name("check")
+      .about("Will add all motions that have not yet been applied inside a transaction which is always rolled back")
+      .arg(&directory_arg)
+      .args(&discovery_args)
+      .args(&tag_args)
+      .args(&driver_args)
+      .args(&tenant_args)
+      .arg(&var_arg)
+      .arg(&allow_out_of_order_arg)
+      .arg(
+        Arg::with_name("sub")
+        .help("Also sub all of the motions after adding them")
+        .long("sub")
+      )
+    )
+    .subcommand(
+      SubCommand::with_name("schema")
+      .about("Describes the schema of your database so that it may be committed and reviewed")
+      .setting(SubcommandRequired)
+      .subcommand(
+        SubCommand::with_name("dump")
+        .about("Writes a description of the database schema to a file, or prints it if no file is given")
+        .args(&driver_args)
+        .arg(
+          Arg::with_name("file")
+          .help("The file to write the schema description to")
+          .value_name("FILE")
+        )
+      )
+      .subcommand(
+        SubCommand::with_name("check")
+        .about("Fails if the schema description in a file does not match the database once all motions are applied")
+        .arg(&directory_arg)
+        .args(&discovery_args)
+        .args(&driver_args)
+        .arg(
+          Arg::with_name("file")
+          .help("The file with the expected schema description")
+          .required(true)
+          .value_name("FILE")
+        )
+      )
     )
   ).get_matches();
 
-  let subcommand_name = matches.subcommand_name().unwrap();
-  let matches = matches.subcommand_matches(subcommand_name).unwrap();
+  let mut subcommand_name = app_matches.subcommand_name().unwrap().to_string();
+  let mut matches = app_matches.subcommand_matches(&subcommand_name).unwrap();
+  // Flatten nested subcommands, like `schema dump`, into a single name.
+  while let Some(name) = matches.subcommand_name() {
+    subcommand_name = subcommand_name + " " + name;
+    matches = matches.subcommand_matches(name).unwrap();
+  }
   let auto_confirm = matches.is_present("auto_confirm");
+  let allow_out_of_order = matches.is_present("allow_out_of_order");
 
   let directory_env = env::var("ACCELERATE_DIRECTORY").ok();
   let driver_name_env = env::var("ACCELERATE_DRIVER").ok();
   let database_env = env::var("ACCELERATE_DATABASE").ok();
+  let dump_schema_env = env::var("ACCELERATE_DUMP_SCHEMA").ok();
+  let tenants_env = env::var("ACCELERATE_TENANTS").ok();
 
   let directory = || Path::new(matches.value_of("directory").or(directory_env.as_ref().map(|s| s.as_str())).unwrap_or("."));
-  let motions = || motions::find(&directory());
+  let namespace = || -> Result<Option<String>, Error> {
+    match matches.value_of("namespace") {
+      Some(namespace) => Ok(Some(namespace.to_string())),
+      None => Ok(try!(Config::read(&directory())).get("namespace").map(String::from)),
+    }
+  };
+
+  let motions = || -> Result<Vec<Motion>, Error> {
+    let config = try!(Config::read(&directory()));
+    let mut options = FindOptions::default();
+    options.namespace = try!(namespace());
+    if let Some(max_depth) = matches.value_of("max_depth").or(config.get("max_depth")) {
+      options.max_depth = try!(max_depth.parse().map_err(|_| error!("Max depth '{}' must be a whole number.", max_depth)));
+    }
+    if let Some(symlinks) = matches.value_of("symlinks").or(config.get("symlinks")) {
+      options.symlinks = try!(symlinks.parse());
+    }
+    if let Some(order) = matches.value_of("order").or(config.get("order")) {
+      options.order = try!(order.parse());
+    }
+    motions::find(&directory(), &options)
+  };
 
-  let driver = || {
+  let driver = || -> Result<Box<Driver>, Error> {
     let driver_name = matches.value_of("driver_name").or(driver_name_env.as_ref().map(|s| s.as_str()));
     let database = matches.value_of("database").or(database_env.as_ref().map(|s| s.as_str()));
     let database = try!(database.ok_or(error!("A database connection string is required and none was found in either the command line arguments or the environment variable `ACCELERATE_DATABASE`.")));
-    driver::get(driver_name, database)
+    let mut driver = try!(driver::get(driver_name, database));
+    if let Some(namespace) = try!(namespace()) {
+      try!(driver.set_namespace(&namespace));
+    }
+    Ok(driver)
+  };
+
+  // Variables from the command line win over those in the config file.
+  let variables = || -> Result<Variables, Error> {
+    let config = try!(Config::read(&directory()));
+    let mut variables = Variables::default();
+    if let Some(enabled) = config.get("variables") {
+      variables.set_enabled(try!(motions::parse_switch(enabled)));
+    }
+    for (name, value) in config.get_prefixed("var.") {
+      variables.set(name, value);
+    }
+    for var in matches.values_of("var").into_iter().flat_map(|values| values) {
+      let mut parts = var.splitn(2, '=');
+      match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => variables.set(name, value),
+        _ => return Err(error!("Variable '{}' must look like `name=value`.", var)),
+      }
+    }
+    Ok(variables)
+  };
+
+  let aliases = || -> Result<Aliases, Error> {
+    Ok(Aliases::from_config(&try!(Config::read(&directory()))))
+  };
+
+
+  let tenants = match (matches.value_of("tenants").or(tenants_env.as_ref().map(|s| s.as_str())), matches.value_of("tenants_query")) {
+    (_, Some(query)) => Some(Tenants::Query(query.to_string())),
+    (Some(pattern), None) => Some(Tenants::Pattern(pattern.to_string())),
+    (None, None) => None,
+  };
+
+  // The schema is only dumped by commands which change the database. Every
+  // tenant would overwrite the dump of the tenant before it, so we refuse
+  // before any of them are changed.
+  let dump_schema_path = match subcommand_name.as_str() {
+    "add" | "sub" | "up" | "down" | "redo" | "reset" => matches.value_of("dump_schema").or(dump_schema_env.as_ref().map(|s| s.as_str())),
+    _ => None,
+  };
+  if dump_schema_path.is_some() && tenants.is_some() {
+    return Err(error!("The schema can not be dumped when applying motions to tenants."));
+  }
+
+  // Finds all of the tenants we will be running against. If we were not asked
+  // to run against tenants we run once against the database as it is.
+  let find_tenants = |driver: &Driver| -> Result<Vec<Option<String>>, Error> {
+    match tenants {
+      Some(ref tenants) => Ok(try!(driver.get_tenants(tenants)).into_iter().map(Some).collect()),
+      None => Ok(vec![None]),
+    }
+  };
+
+  // Whether a motion has the tags we asked for. Without any `--tag` every
+  // motion does, unless it has a tag we excluded.
+  let tags = matches.values_of("tag").map(|tags| tags.collect::<Vec<&str>>()).unwrap_or(Vec::new());
+  let exclude_tags = matches.values_of("exclude_tag").map(|tags| tags.collect::<Vec<&str>>()).unwrap_or(Vec::new());
+  let is_tagged = |motion: &Motion| -> bool {
+    let motion_tags = motion.tags(&directory());
+    let has_tag = |tag: &&str| motion_tags.iter().any(|motion_tag| motion_tag == tag);
+    (tags.is_empty() || tags.iter().any(&has_tag)) && !exclude_tags.iter().any(&has_tag)
+  };
+
+  // Runs a command with an accelerator for every tenant. All of the tenants
+  // share a single connection. Commands which change the database first bring
+  // the records of renamed and squashed motions up to date.
+  let for_each_tenant = |reconcile: bool, command: &mut FnMut(&mut Accelerator) -> Result<(), Error>| -> Result<(), Error> {
+    // Every tenant gets the same motions, so we only look for them once.
+    let motions = try!(motions());
+    let variables = try!(variables());
+    let aliases = try!(aliases());
+    let mut driver = try!(driver());
+    for tenant in try!(find_tenants(&*driver)) {
+      if let Some(ref tenant) = tenant {
+        println!("{} {}", "Tenant".bold(), tenant);
+        try!(driver.set_tenant(tenant));
+      }
+      let mut accelerator = try!(Accelerator::new(driver, motions.clone(), variables.clone(), &aliases, &is_tagged));
+      if reconcile { try!(accelerator.reconcile_records()); }
+      try!(command(&mut accelerator));
+      driver = accelerator.into_driver();
+    }
+    Ok(())
   };
 
-  let accelerator = || Accelerator::new(try!(driver()), try!(motions()));
+  // After changing the database, dump its schema if we were asked to.
+  let dump_schema = |accelerator: &Accelerator| -> Result<(), Error> {
+    if let Some(path) = dump_schema_path {
+      try!(schema::write(Path::new(path), &try!(accelerator.dump_schema())));
+    }
+    Ok(())
+  };
 
-  match subcommand_name {
+  match subcommand_name.as_str() {
     "ls" => {
-      for motion in try!(motions()) {
-        println!("{}", motion);
+      for motion in try!(motions()).iter().filter(|motion| is_tagged(motion)) {
+        print_motion(None, motion);
+      }
+    },
+    "status" if tenants.is_some() => {
+      // Print a table with a row for every tenant.
+      let mut driver = try!(driver());
+      let aliases = try!(aliases());
+      let motions = try!(motions()).into_iter().filter(|motion| is_tagged(motion)).collect::<Vec<Motion>>();
+      let mut rows = Vec::new();
+      for tenant in try!(find_tenants(&*driver)) {
+        let tenant = tenant.unwrap();
+        try!(driver.set_tenant(&tenant));
+        let records = try!(driver.get_records()).into_iter().map(|record| Record { name: aliases.resolve(&record.name).to_string(), ..record }).collect::<Vec<Record>>();
+        let statuses = try!(accelerator::find_statuses(&records, &try!(driver.get_repeatable_records()), &motions));
+        let count = |wanted: &[Status]| statuses.iter().filter(|status| wanted.contains(status)).count();
+        let last = motions.iter().zip(statuses.iter()).filter(|&(_, status)| *status == Status::Applied || *status == Status::Changed).map(|(motion, _)| motion.to_string()).last();
+        rows.push((
+          tenant,
+          count(&[Status::Applied, Status::Changed]),
+          count(&[Status::Changed]),
+          count(&[Status::Late, Status::Unapplied]),
+          last.unwrap_or(String::new())
+        ));
+      }
+      let width = rows.iter().map(|row| row.0.chars().count()).chain(Some("Tenant".len())).max().unwrap();
+      println!("{:<width$}  {:>7}  {:>7}  {:>7}  {}", "Tenant", "Applied", "Changed", "Pending", "Last", width = width);
+      for &(ref tenant, applied, changed, unapplied, ref last) in rows.iter() {
+        let mark = if unapplied > 0 { "𝙭".red().bold() } else if changed > 0 { "!".yellow().bold() } else { "✔".green().bold() };
+        println!("{:<width$}  {:>7}  {:>7}  {:>7}  {} {}", tenant, applied, changed, unapplied, mark, last, width = width);
+      }
+      for &(ref tenant, _, _, _, _) in rows.iter().filter(|row| row.2 > 0) {
+        println!(
+          "{} Motions have changed since they were applied to the '{}' tenant. If the changes are intended, accept them with `accelerate accept-checksum`.",
+          "Warning:".yellow().bold(),
+          tenant
+        );
       }
     },
     "status" => {
       let driver = try!(driver());
-      let records = try!(driver.get_records());
-      for motion in try!(motions()) {
-        if records.contains(&motion.name) {
-          println!("{} {}", "✔".green().bold(), motion);
-        } else {
-          println!("{} {}", "𝙭".red().bold(), motion);
+      // Records may still have the old name of a renamed motion.
+      let aliases = try!(aliases());
+      let records = try!(driver.get_records()).into_iter().map(|record| Record { name: aliases.resolve(&record.name).to_string(), ..record }).collect::<Vec<Record>>();
+      let motions = try!(motions()).into_iter().filter(|motion| is_tagged(motion)).collect::<Vec<Motion>>();
+      let statuses = try!(accelerator::find_statuses(&records, &try!(driver.get_repeatable_records()), &motions));
+      // A repeatable motion is only up to date if it has not changed since it
+      // was last executed. Any other motion which has changed since it was
+      // applied is a problem we should warn about.
+      for (motion, status) in motions.iter().zip(statuses.iter()) {
+        let mark = match *status {
+          Status::Applied | Status::Repeated => "✔".green().bold(),
+          Status::Changed => "!".yellow().bold(),
+          Status::Late => "↩".yellow().bold(),
+          Status::Unapplied | Status::Outdated => "𝙭".red().bold(),
+        };
+        print_motion(Some(mark), motion);
+      }
+      for (motion, status) in motions.iter().zip(statuses.iter()) {
+        if *status == Status::Changed {
+          println!(
+            "{} The '{}' motion has changed since it was applied. If the change is intended, accept it with `accelerate accept-checksum {}`.",
+            "Warning:".yellow().bold(),
+            motion,
+            motion.name
+          );
+        }
+      }
+      for (motion, status) in motions.iter().zip(statuses.iter()) {
+        if *status == Status::Late {
+          println!("{} {}", "Warning:".yellow().bold(), accelerator::late_message(motion));
         }
       }
     },
+    "accept-checksum" => {
+      let name = matches.value_of("motion").unwrap();
+      let motions = try!(motions());
+      let motion = try!(
+        motions
+        .iter()
+        .find(|motion| motion.name == name || motion.to_string() == name)
+        .ok_or(error!("Motion '{}' could not be found.", name))
+      );
+      if motion.repeatable {
+        return Err(error!("The '{}' motion is repeatable so it will be executed again when it changes. There is no checksum to accept.", motion));
+      }
+      let record = Record { name: motion.name.clone(), checksum: Some(try!(motion.checksum())) };
+      let mut driver = try!(driver());
+      for tenant in try!(find_tenants(&*driver)) {
+        if let Some(ref tenant) = tenant {
+          println!("{} {}", "Tenant".bold(), tenant);
+          try!(driver.set_tenant(tenant));
+        }
+        if !try!(driver.get_records()).iter().any(|record| record.name == motion.name) {
+          return Err(error!("The '{}' motion has not been applied so there is no checksum to accept.", motion));
+        }
+        try!(driver.set_checksum(&record));
+        println!("{} {}", "Accept".yellow().bold(), motion);
+      }
+    },
+    "adopt-records" => {
+      if try!(namespace()).is_none() {
+        return Err(error!("Records can only be adopted into a namespace. Set one with `--namespace` or `namespace` in the config file."));
+      }
+      let motions = try!(motions());
+      let aliases = try!(aliases());
+      let mut driver = try!(driver());
+      for tenant in try!(find_tenants(&*driver)) {
+        if let Some(ref tenant) = tenant {
+          println!("{} {}", "Tenant".bold(), tenant);
+          try!(driver.set_tenant(tenant));
+        }
+        let names = try!(accelerator::find_adoptable(&*driver, &motions, &aliases));
+        if names.is_empty() { continue; }
+        try!(driver.begin());
+        if let Err(error) = driver.adopt_records(&names) {
+          let _ = driver.rollback();
+          return Err(error);
+        }
+        try!(driver.commit());
+        for name in names.iter() {
+          println!("{} {}", "Adopt".blue().bold(), motions.iter().find(|motion| motion.name == aliases.resolve(name)).unwrap());
+        }
+      }
+    },
+    "rename" => {
+      let name = matches.value_of("motion").unwrap();
+      let new_name = matches.value_of("name").unwrap();
+      let motions = try!(motions());
+      let motion = try!(
+        motions
+        .iter()
+        .find(|motion| motion.name == name || motion.to_string() == name)
+        .ok_or(error!("Motion '{}' could not be found.", name))
+      );
+      if motions.iter().any(|other| other.name == new_name) {
+        return Err(error!("There is already a motion named '{}'.", new_name));
+      }
+      if let Some(other) = motions.iter().find(|other| other.meta.requires.contains(&motion.name)) {
+        return Err(error!("The '{}' motion requires the '{}' motion. Change its requirements before renaming.", other, motion));
+      }
+      // Connect before changing any files, so a database we can’t reach doesn’t
+      // leave us half done.
+      let mut driver = try!(driver());
+      let tenants = try!(find_tenants(&*driver));
+      let renamed = try!(motion.rename(new_name));
+      println!("{} {} to {}", "Rename".blue().bold(), motion, renamed);
+      // Every tenant shares our connection, so all of their records are renamed
+      // in one transaction.
+      try!(driver.begin());
+      let result = (|| -> Result<(), Error> {
+        for tenant in tenants.iter() {
+          if let Some(ref tenant) = *tenant {
+            println!("{} {}", "Tenant".bold(), tenant);
+            try!(driver.set_tenant(tenant));
+          }
+          if try!(driver.rename_record(&motion.name, new_name)) {
+            println!("{} {}", "Record".blue().bold(), renamed);
+          }
+        }
+        driver.commit()
+      })();
+      if let Err(error) = result {
+        // Put the files back so the motion keeps its old name everywhere.
+        let _ = driver.rollback();
+        let _ = renamed.rename(&motion.name);
+        return Err(error);
+      }
+      // The alias lets every other database with a record of the old name
+      // catch up the next time it sees these motions. It is written last so
+      // a rename which failed never leaves one behind.
+      try!(Config::append(&directory(), &format!("alias.{}", motion.name), new_name));
+    },
+    "squash" => {
+      let until = matches.value_of("until").unwrap();
+      let motions = try!(motions());
+      // Repeatable motions aren’t applied in order, so they are never squashed.
+      let ordered = motions.iter().filter(|motion| !motion.repeatable).cloned().collect::<Vec<Motion>>();
+      let index = try!(
+        ordered
+        .iter()
+        .position(|motion| motion.name == until || motion.to_string() == until)
+        .ok_or(error!("Motion '{}' could not be found.", until))
+      );
+      let squashed = &ordered[..index + 1];
+      if squashed.len() < 2 {
+        return Err(error!("There is nothing to squash into the '{}' motion as it is the first.", squashed[0]));
+      }
+      let name = match matches.value_of("name") {
+        Some(name) => name.to_string(),
+        None => format!("{}-baseline", squashed[index].name.split('-').next().unwrap()),
+      };
+      if motions.iter().any(|motion| motion.name == name && !squashed.contains(motion)) {
+        return Err(error!("There is already a motion named '{}'.", name));
+      }
+      if let Some(motion) = motions.iter().filter(|motion| !squashed.contains(motion)).find(|motion| squashed.iter().any(|squashed| motion.meta.requires.contains(&squashed.name))) {
+        return Err(error!("The '{}' motion requires a motion which would be squashed. Change its requirements before squashing.", motion));
+      }
+      // Every database must have applied all of the motions we squash or none
+      // of them, otherwise it can’t be recorded as having the squashed motion.
+      let mut driver = try!(driver());
+      let tenants = try!(find_tenants(&*driver));
+      for tenant in tenants.iter() {
+        if let Some(ref tenant) = *tenant { try!(driver.set_tenant(tenant)); }
+        let records = try!(driver.get_records());
+        let applied = squashed.iter().filter(|motion| records.iter().any(|record| record.name == motion.name)).count();
+        if applied > 0 && applied < squashed.len() {
+          return Err(error!("Only {} of the {} motions up to the '{}' motion have been applied. Apply them all before squashing.", applied, squashed.len(), squashed[index]));
+        }
+      }
+      let squash = try!(motions::squash(&directory(), squashed, &name));
+      // The config file remembers what we squashed so other databases can catch
+      // up the next time they see these motions.
+      try!(Config::append(&directory(), &format!("squash.{}", name), &squashed.iter().map(|motion| motion.name.as_str()).collect::<Vec<&str>>().join(", ")));
+      // Now record the squashed motion in every database which applied the
+      // motions it replaces.
+      let motions = motions.iter().filter(|motion| !squashed.contains(motion)).cloned().chain(Some(squash)).collect::<Vec<Motion>>();
+      let aliases = try!(aliases());
+      for tenant in tenants.iter() {
+        if let Some(ref tenant) = *tenant {
+          println!("{} {}", "Tenant".bold(), tenant);
+          try!(driver.set_tenant(tenant));
+        }
+        try!(accelerator::record_squashes(&mut *driver, &motions, &aliases));
+      }
+    },
     "create" => {
-      try!(motions::create(&directory(), matches.value_of("name").unwrap()));
+      let config = try!(Config::read(&directory()));
+      let naming = match matches.value_of("naming").or(config.get("naming")) {
+        Some(naming) => try!(naming.parse()),
+        None => Naming::Minutes,
+      };
+      try!(motions::create(&directory(), matches.value_of("name").unwrap(), matches.is_present("single"), matches.value_of("template"), naming, matches.value_of("from").map(Path::new)));
+    },
+    "gen-sub" => {
+      let name = matches.value_of("motion").unwrap();
+      let motions = try!(motions());
+      let motion = try!(
+        motions
+        .iter()
+        .find(|motion| motion.name == name || motion.to_string() == name)
+        .ok_or(error!("Motion '{}' could not be found.", name))
+      );
+      // A sub file is part of the checksum of a motion, so a database which
+      // applied the motion must accept its new checksum. Without a database we
+      // only write the file, and `accept-checksum` is suggested later.
+      let (driver, applied) = if matches.is_present("database") || database_env.is_some() {
+        let mut driver = try!(driver());
+        let mut applied = Vec::new();
+        for tenant in try!(find_tenants(&*driver)) {
+          if let Some(ref tenant) = tenant { try!(driver.set_tenant(tenant)); }
+          if try!(driver.get_records()).iter().any(|record| record.name == motion.name) {
+            applied.push(tenant);
+          }
+        }
+        (Some(driver), applied)
+      } else {
+        (None, Vec::new())
+      };
+      let path = try!(motions::generate_sub(motion));
+      println!("{} {}", "Create".green().bold(), path.display());
+      if let Some(mut driver) = driver {
+        let motion = Motion { sub_path: Some(path), ..motion.clone() };
+        let record = Record { name: motion.name.clone(), checksum: Some(try!(motion.checksum())) };
+        for tenant in applied {
+          if let Some(ref tenant) = tenant {
+            println!("{} {}", "Tenant".bold(), tenant);
+            try!(driver.set_tenant(tenant));
+          }
+          try!(driver.set_checksum(&record));
+          println!("{} {}", "Accept".yellow().bold(), motion);
+        }
+      }
     },
     "add" => {
-      let mut accelerator = try!(accelerator());
       let n = try!(matches.value_of("n").unwrap_or("1").parse::<usize>());
-      try!(accelerator.add(n));
+      try!(for_each_tenant(true, &mut |accelerator| {
+        try!(accelerator.add(n));
+        dump_schema(accelerator)
+      }));
     },
     "sub" => {
       if !auto_confirm { try!(confirm()); }
-      let mut accelerator = try!(accelerator());
       let n = try!(matches.value_of("n").unwrap_or("1").parse::<usize>());
-      try!(accelerator.sub(n));
+      try!(for_each_tenant(true, &mut |accelerator| {
+        try!(accelerator.sub(n));
+        dump_schema(accelerator)
+      }));
     },
     "up" => {
-      let mut accelerator = try!(accelerator());
-      try!(accelerator.add(usize::max_value()));
+      try!(for_each_tenant(true, &mut |accelerator| {
+        accelerator.set_allow_out_of_order(allow_out_of_order);
+        try!(accelerator.add(usize::max_value()));
+        try!(accelerator.repeat(false));
+        dump_schema(accelerator)
+      }));
     },
     "down" => {
       if !auto_confirm { try!(confirm()); }
-      let mut accelerator = try!(accelerator());
-      try!(accelerator.sub(usize::max_value()));
+      try!(for_each_tenant(true, &mut |accelerator| {
+        try!(accelerator.sub(usize::max_value()));
+        dump_schema(accelerator)
+      }));
     },
     "redo" => {
       if !auto_confirm { try!(confirm()); }
-      let mut accelerator = try!(accelerator());
-      try!(accelerator.sub(1));
-      try!(accelerator.add(1));
+      try!(for_each_tenant(true, &mut |accelerator| {
+        accelerator.set_allow_out_of_order(allow_out_of_order);
+        // Check before subbing so late motions can’t leave us half done. If
+        // they are allowed they are added first, so add them as well.
+        try!(accelerator.check_order());
+        let late = accelerator.late_count();
+        try!(accelerator.sub(1));
+        try!(accelerator.add(1 + late));
+        dump_schema(accelerator)
+      }));
     },
     "reset" => {
       if !auto_confirm { try!(confirm()); }
-      let mut accelerator = try!(accelerator());
-      let applied = accelerator.applied_count();
-      try!(accelerator.sub(usize::max_value()));
-      try!(accelerator.add(applied));
+      try!(for_each_tenant(true, &mut |accelerator| {
+        accelerator.set_allow_out_of_order(allow_out_of_order);
+        try!(accelerator.check_order());
+        let applied = accelerator.applied_count() + accelerator.late_count();
+        try!(accelerator.sub(usize::max_value()));
+        try!(accelerator.add(applied));
+        // Subbing may have dropped whatever the repeatable motions created, so
+        // execute them all again once everything has been added.
+        if accelerator.unapplied_count() == 0 { try!(accelerator.repeat(true)); }
+        dump_schema(accelerator)
+      }));
+    },
+    "check" => {
+      let sub = matches.is_present("sub");
+      try!(for_each_tenant(false, &mut |accelerator| {
+        accelerator.set_allow_out_of_order(allow_out_of_order);
+        accelerator.check(sub)
+      }));
+    },
+    "schema dump" => {
+      let schema = try!(try!(driver()).dump_schema());
+      match matches.value_of("file") {
+        Some(path) => try!(schema::write(Path::new(path), &schema)),
+        None => print!("{}", schema),
+      }
+    },
+    "schema check" => {
+      let accelerator = try!(Accelerator::new(try!(driver()), try!(motions()), try!(variables()), &try!(aliases()), |_| true));
+      // The schema file describes the database with every motion applied, so
+      // comparing against anything less would be meaningless.
+      let unapplied = accelerator.unapplied_count();
+      if unapplied != 0 {
+        return Err(error!("There are {} motions which have not been applied. Apply them with `accelerate up` before checking the schema.", unapplied));
+      }
+      try!(schema::check(Path::new(matches.value_of("file").unwrap()), &try!(accelerator.dump_schema())));
     },
     _ => unreachable!(),
   }
@@ -231,6 +927,19 @@ fn run() -> Result<(), Error> {
   Ok(())
 }
 
+/// Prints a motion on its own line with an optional mark in front and a
+/// summary of its metadata after.
+fn print_motion(mark: Option<ColoredString>, motion: &Motion) {
+  let mut line = String::new();
+  if let Some(mark) = mark { line.push_str(&format!("{} ", mark)); }
+  line.push_str(&motion.to_string());
+  if motion.repeatable { line.push_str(&format!(" {}", "(repeatable)".cyan())); }
+  else if motion.is_irreversible() { line.push_str(&format!(" {}", "(irreversible)".yellow())); }
+  let summary = motion.meta.summary();
+  if !summary.is_empty() { line.push_str(&format!("  {}", summary.dimmed())); }
+  println!("{}", line);
+}
+
 fn confirm() -> Result<(), Error> {
   // Display a warning message.
   println!("{} You may be removing information by proceeding. Do you wish to continue? (y/n)", "Warning:".yellow().bold());
@@ -1,25 +1,170 @@
-use std::time::{UNIX_EPOCH, SystemTime};
+use std::time::{UNIX_EPOCH, Duration, SystemTime};
+use std::env;
 use std::fmt;
+use std::cmp;
+use std::str::FromStr;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::io::prelude::*;
 use std::fs;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::collections::BTreeMap;
 use colored::Colorize;
 use regex;
 use regex::Regex;
 use error::Error;
+use source::Source;
+use checksum::checksum;
+use invert;
+use ignore::{self, Ignore};
+use config::{self, Config};
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Clone, Default, Debug)]
 pub struct Motion {
   pub name: String,
   pub add_path: PathBuf,
-  pub sub_path: PathBuf,
+  /// The path of the file which subs this motion. Motions without one are
+  /// irreversible.
+  pub sub_path: Option<PathBuf>,
+  /// Whether the add and sub halves of this motion are sections in a single
+  /// file instead of separate files. If so both paths are the same.
+  pub single: bool,
+  /// Whether this motion is executed again every time its contents change
+  /// instead of being applied once. Repeatable motions are never subbed.
+  pub repeatable: bool,
+  pub meta: Metadata,
+}
+
+/// Information about a motion from the header at the top of its add file. A
+/// header is made of comment lines like `-- ticket: ABC-123`.
+#[derive(Eq, PartialEq, Clone, Default, Debug)]
+pub struct Metadata {
+  pub description: Option<String>,
+  pub author: Option<String>,
+  pub ticket: Option<String>,
+  pub tags: Vec<String>,
+  /// The names of other motions which must be applied before this one.
+  pub requires: Vec<String>,
+  /// Whether the motion can not be subbed, even if it has a sub file.
+  pub irreversible: bool,
+  /// Whether the motion should be executed in a transaction of its own. If
+  /// not set the motion manages its own transactions.
+  pub transaction: Option<bool>,
+  /// How long the motion may take to execute before it is cancelled.
+  pub timeout: Option<Duration>,
+  /// Whether `${name}` placeholders in the motion are filled in. If not set
+  /// the `variables` setting in the config file decides.
+  pub variables: Option<bool>,
+}
+
+impl Metadata {
+  /// A short human description of the motion, empty if there is nothing to
+  /// say.
+  pub fn summary(&self) -> String {
+    let mut parts = Vec::new();
+    if let Some(ref description) = self.description { parts.push(description.clone()); }
+    if let Some(ref ticket) = self.ticket { parts.push(format!("({})", ticket)); }
+    if let Some(ref author) = self.author { parts.push(format!("by {}", author)); }
+    if !self.tags.is_empty() { parts.push(format!("[{}]", self.tags.join(", "))); }
+    parts.join(" ")
+  }
+}
+
+impl Motion {
+  /// Reads the source which adds this motion, along with any files it
+  /// includes.
+  pub fn read_add(&self) -> Result<Source, Error> {
+    try!(if self.single { read_section(&self.add_path, "add") } else { Source::read(&self.add_path) }).read_includes()
+  }
+
+  /// Reads the source which subs this motion.
+  pub fn read_sub(&self) -> Result<Source, Error> {
+    if self.is_irreversible() { return Err(error!("The '{}' motion is irreversible and can not be subbed.", self)); }
+    self.read_sub_path().map(Option::unwrap)
+  }
+
+  /// Reads the sub source of this motion if it has one, even if the motion
+  /// says it is irreversible.
+  fn read_sub_path(&self) -> Result<Option<Source>, Error> {
+    match self.sub_path {
+      Some(ref sub_path) => Ok(Some(try!(try!(if self.single { read_section(sub_path, "sub") } else { Source::read(sub_path) }).read_includes()))),
+      None => Ok(None),
+    }
+  }
+
+  /// Whether this motion can never be subbed, either because it has nothing
+  /// to sub it with or because it says so in its header.
+  pub fn is_irreversible(&self) -> bool {
+    self.sub_path.is_none() || self.meta.irreversible
+  }
+
+  /// A checksum of the sources which add and sub this motion, used to tell
+  /// when a motion has changed since it was executed.
+  pub fn checksum(&self) -> Result<String, Error> {
+    let mut text = try!(self.read_add()).full_text();
+    if let Some(sub) = try!(self.read_sub_path()) { text.push_str(&sub.full_text()); }
+    Ok(checksum(&text))
+  }
+
+  /// The tags of this motion, from the directories it is in below the motions
+  /// directory, the parts of its name after a dot like `seed` in
+  /// `123456-users.seed`, and its header.
+  pub fn tags(&self, root: &Path) -> Vec<String> {
+    let mut tags = Vec::new();
+    if let Some(parent) = self.add_path.parent().and_then(|parent| parent.strip_prefix(root).ok()) {
+      tags.extend(parent.components().map(|component| component.as_os_str().to_string_lossy().into_owned()));
+    }
+    tags.extend(self.name.split('.').skip(1).map(String::from));
+    tags.extend(self.meta.tags.iter().cloned());
+    let mut unique = Vec::new();
+    for tag in tags {
+      if !unique.contains(&tag) { unique.push(tag); }
+    }
+    unique
+  }
+
+  /// Renames the files of this motion so it has a new name, keeping them in
+  /// the same directory. Nothing is renamed if any of the new files exist.
+  pub fn rename(&self, new_name: &str) -> Result<Motion, Error> {
+    if new_name.is_empty() || new_name.contains('/') || new_name.contains('\\') {
+      return Err(error!("The name '{}' can not be used for a motion, names may not be empty or have a directory.", new_name));
+    }
+    // Every motion file name starts with the motion name, so we only replace
+    // that part.
+    let renamed_path = |path: &Path| {
+      let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+      path.with_file_name(new_name.to_owned() + &file_name[self.name.len()..])
+    };
+    let mut paths = vec![(self.add_path.clone(), renamed_path(&self.add_path))];
+    if let Some(ref sub_path) = self.sub_path {
+      if *sub_path != self.add_path { paths.push((sub_path.clone(), renamed_path(sub_path))); }
+    }
+    if let Some(&(_, ref new_path)) = paths.iter().find(|&&(_, ref new_path)| new_path.exists()) {
+      return Err(error!("The '{}' motion can not be renamed because '{}' already exists.", self, new_path.display()));
+    }
+    // If a file can’t be renamed, put back those which were so the motion is
+    // never left with two names.
+    for (i, &(ref path, ref new_path)) in paths.iter().enumerate() {
+      if let Err(error) = fs::rename(path, new_path) {
+        for &(ref path, ref new_path) in paths[..i].iter().rev() {
+          let _ = fs::rename(new_path, path);
+        }
+        return Err(Box::new(error));
+      }
+    }
+    Ok(Motion {
+      name: new_name.to_string(),
+      add_path: renamed_path(&self.add_path),
+      sub_path: self.sub_path.as_ref().map(|sub_path| renamed_path(sub_path)),
+      ..self.clone()
+    })
+  }
 }
 
 impl fmt::Display for Motion {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    let parent_display = format!("{}", self.add_path.parent().unwrap().display());
+    let parent_display = self.add_path.parent().map(|parent| format!("{}", parent.display())).unwrap_or(String::new());
     try!(write!(f, "{}", parent_display));
     if parent_display != "" { try!(write!(f, "/")); }
     try!(write!(f, "{}", self.name));
@@ -27,63 +172,314 @@ impl fmt::Display for Motion {
   }
 }
 
+/// The templates new motions are created from. A directory may have an add and
+/// a sub template, a single template with both sections, or all three.
 #[derive(Eq, PartialEq, Debug)]
 struct Template {
   extension: String,
-  add_path: PathBuf,
-  sub_path: PathBuf,
+  add_path: Option<PathBuf>,
+  sub_path: Option<PathBuf>,
+  single_path: Option<PathBuf>,
+}
+
+/// The file name patterns for motions which use a template’s extension.
+struct Patterns {
+  add: Regex,
+  sub: Regex,
+  /// Without an extension any file at all could be a single file motion, so
+  /// there are only single file motions when there is one.
+  single: Option<Regex>,
+  always: Regex,
+}
+
+impl Patterns {
+  fn new(extension: &str) -> Patterns {
+    let extension = regex::quote(extension);
+    Patterns {
+      add: Regex::new(&(r"^(.+)\.add".to_owned() + &extension + "$")).unwrap(),
+      sub: Regex::new(&(r"^(.+)\.sub".to_owned() + &extension + "$")).unwrap(),
+      single: if extension.is_empty() { None } else { Some(Regex::new(&(r"^(.+)".to_owned() + &extension + "$")).unwrap()) },
+      always: Regex::new(&(r"^(.+)\.always".to_owned() + &extension + "$")).unwrap(),
+    }
+  }
+}
+
+/// What to do with symbolic links found while discovering motions.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Symlinks {
+  /// Look through links as if they were the files or directories they point
+  /// to. Links which loop back on a directory we are already in are an error.
+  Follow,
+  /// Pretend links aren’t there.
+  Skip,
+  /// Refuse to discover motions if there is a link anywhere.
+  Error,
+}
+
+impl FromStr for Symlinks {
+  type Err = Error;
+
+  fn from_str(string: &str) -> Result<Symlinks, Error> {
+    match string {
+      "follow" => Ok(Symlinks::Follow),
+      "skip" => Ok(Symlinks::Skip),
+      "error" => Ok(Symlinks::Error),
+      _ => Err(error!("Symlink policy '{}' is not one of `follow`, `skip` or `error`.", string)),
+    }
+  }
+}
+
+/// How the names of new motions are prefixed so that they sort in the order
+/// they were created.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Naming {
+  /// Unix time in minutes, like `24451200-users`.
+  Minutes,
+  /// Unix time in seconds, like `1467072000-users`.
+  Seconds,
+  /// The UTC date and time, like `20160628000000-users`.
+  DateTime,
+  /// One more than the highest number of any motion, like `0042-users`.
+  Sequential,
 }
 
-pub fn find(dir: &Path) -> Result<Vec<Motion>, Error> {
-  let template = try!(find_template(dir));
-  let motions = try!(find_motions(&template, dir));
+impl FromStr for Naming {
+  type Err = Error;
+
+  fn from_str(string: &str) -> Result<Naming, Error> {
+    match string {
+      "minutes" => Ok(Naming::Minutes),
+      "seconds" => Ok(Naming::Seconds),
+      "datetime" => Ok(Naming::DateTime),
+      "sequential" => Ok(Naming::Sequential),
+      _ => Err(error!("Naming scheme '{}' is not one of `minutes`, `seconds`, `datetime` or `sequential`.", string)),
+    }
+  }
+}
+
+/// How motion names are sorted.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Order {
+  /// Runs of digits are compared as numbers, so `9-init` comes before
+  /// `10-users`.
+  Natural,
+  /// Names are compared character by character, so `10-users` comes before
+  /// `9-init`.
+  Lexical,
+}
+
+impl FromStr for Order {
+  type Err = Error;
+
+  fn from_str(string: &str) -> Result<Order, Error> {
+    match string {
+      "natural" => Ok(Order::Natural),
+      "lexical" => Ok(Order::Lexical),
+      _ => Err(error!("Order '{}' is not one of `natural` or `lexical`.", string)),
+    }
+  }
+}
+
+impl Order {
+  pub fn compare(&self, a: &str, b: &str) -> cmp::Ordering {
+    match *self {
+      Order::Lexical => a.cmp(b),
+      // Names which are only different in their zeros, like `01-a` and `1-a`,
+      // still need an order so fall back to comparing them lexically.
+      Order::Natural => match compare_chunks(a, b) {
+        cmp::Ordering::Equal => a.cmp(b),
+        ordering => ordering,
+      },
+    }
+  }
+}
+
+/// Compares two strings a run of digits or a run of anything else at a time.
+/// Runs of digits are compared by their value, however many digits they have.
+fn compare_chunks(a: &str, b: &str) -> cmp::Ordering {
+  let mut a_chunks = chunks(a).into_iter();
+  let mut b_chunks = chunks(b).into_iter();
+  loop {
+    match (a_chunks.next(), b_chunks.next()) {
+      (None, None) => return cmp::Ordering::Equal,
+      (None, Some(_)) => return cmp::Ordering::Less,
+      (Some(_), None) => return cmp::Ordering::Greater,
+      (Some(a_chunk), Some(b_chunk)) => {
+        let is_number = |chunk: &str| chunk.chars().next().map_or(false, |c| c.is_digit(10));
+        let ordering = if is_number(a_chunk) && is_number(b_chunk) {
+          // Compare numbers without their leading zeros, first by how many
+          // digits they have and then digit by digit, so they can be as long
+          // as they like.
+          let a_digits = a_chunk.trim_left_matches('0');
+          let b_digits = b_chunk.trim_left_matches('0');
+          match a_digits.len().cmp(&b_digits.len()) {
+            cmp::Ordering::Equal => a_digits.cmp(b_digits),
+            ordering => ordering,
+          }
+        } else {
+          a_chunk.cmp(b_chunk)
+        };
+        if ordering != cmp::Ordering::Equal { return ordering; }
+      },
+    }
+  }
+}
+
+/// Splits a string into runs of digits and runs of anything else. Sorting
+/// calls this for every comparison so we scan the characters ourselves rather
+/// than compile a regular expression each time.
+fn chunks(string: &str) -> Vec<&str> {
+  let mut chunks = Vec::new();
+  let mut start = 0;
+  let mut was_digit = None;
+  for (index, c) in string.char_indices() {
+    let is_digit = c.is_digit(10);
+    if was_digit.map_or(false, |was_digit| was_digit != is_digit) {
+      chunks.push(&string[start..index]);
+      start = index;
+    }
+    was_digit = Some(is_digit);
+  }
+  if start < string.len() { chunks.push(&string[start..]); }
+  chunks
+}
+
+/// How far and through what motion discovery goes.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct FindOptions {
+  /// How many directories deep to look, where 1 is only the files directly in
+  /// the motions directory.
+  pub max_depth: usize,
+  pub symlinks: Symlinks,
+  /// How motions are sorted before they are ordered by their requirements.
+  pub order: Order,
+  /// The namespace our motions belong to. Directories below ours with a config
+  /// file for another namespace belong to someone else and are skipped.
+  pub namespace: Option<String>,
+}
+
+impl Default for FindOptions {
+  fn default() -> FindOptions {
+    FindOptions {
+      max_depth: 100,
+      symlinks: Symlinks::Follow,
+      order: Order::Natural,
+      namespace: None,
+    }
+  }
+}
+
+pub fn find(dir: &Path, options: &FindOptions) -> Result<Vec<Motion>, Error> {
+  let motions = try!(find_motions(dir, options));
   Ok(motions)
 }
 
-fn find_template(dir: &Path) -> Result<Template, Error> {
-  let paths = try!(find_paths(dir.to_path_buf(), 1));
+/// Finds the template closest to a directory, looking in the directory itself
+/// and then in each of its parents until we reach the root motions directory.
+fn find_nearest_template(root: &Path, dir: &Path, name: Option<&str>) -> Result<Template, Error> {
+  let mut current = Some(dir);
+  while let Some(dir) = current {
+    // We may be creating a motion in a directory which doesn’t exist yet.
+    if dir.is_dir() {
+      if let Some(template) = try!(find_template(dir, name)) { return Ok(template); }
+    }
+    if dir == root || !dir.starts_with(root) { break; }
+    current = dir.parent();
+  }
+  match name {
+    Some(name) => Err(error!("Template '{}' was not found for directory '{}'.", name, dir.display())),
+    None => Err(error!("Add template file was not found for directory '{}'.", dir.display())),
+  }
+}
+
+/// Whether a motion name is really the name of a template, either the
+/// default template or a named one.
+fn is_template_name(name: &str) -> bool {
+  name == "template" || name.starts_with("template.")
+}
+
+/// Finds the templates in a directory, if there are any. Without a name we
+/// find the default templates like `template.add.sql`, otherwise we find the
+/// named ones like `template.table.add.sql`.
+fn find_template(dir: &Path, name: Option<&str>) -> Result<Option<Template>, Error> {
+  let paths = try!(find_paths(dir.to_path_buf(), &FindOptions { max_depth: 1, ..FindOptions::default() }));
 
   // Create the regexi which will match our template file names.
-  let add_re = Regex::new(r"^template\.add($|\..+$)").unwrap();
-  let sub_re = Regex::new(r"^template\.sub($|\..+$)").unwrap();
-
-  // Find our add and sub paths in our list of paths. Error if we can’t find
-  // anything.
-  let add_path = try!(
-    paths.iter()
-    .find(|path| path.file_name().and_then(OsStr::to_str).map(|s| add_re.is_match(s)).unwrap_or(false))
-    .ok_or(error!("Add template file was not found for directory '{}'.", dir.display()))
-  );
-
-  let sub_path = try!(
-    paths.iter()
-    .find(|path| path.file_name().and_then(OsStr::to_str).map(|s| sub_re.is_match(s)).unwrap_or(false))
-    .ok_or(error!("Sub template file was not found for directory '{}'.", dir.display()))
-  );
-
-  // Get both the add extension and the sub extension. We can safely unwrap the
-  // file names because part of being an `add_path` or `sub_path` means we
-  // already checked that a file name exists.
-  let add_ext = add_re.replace_all(add_path.file_name().unwrap().to_str().unwrap(), "$1");
-  let sub_ext = sub_re.replace_all(sub_path.file_name().unwrap().to_str().unwrap(), "$1");
-
-  // If the extensions are not equal, there is an error.
-  if add_ext != sub_ext {
-    return Err(error!("Template extensions for add ('{}') and sub ('{}') do not match.", add_ext, sub_ext))
+  let prefix = match name { Some(name) => r"^template\.".to_owned() + &regex::quote(name), None => r"^template".to_owned() };
+  let add_re = Regex::new(&(prefix.clone() + r"\.add($|\..+$)")).unwrap();
+  let sub_re = Regex::new(&(prefix.clone() + r"\.sub($|\..+$)")).unwrap();
+  let single_re = Regex::new(&(prefix + r"($|\..+$)")).unwrap();
+
+  // Find our add and sub paths in our list of paths.
+  let add_path = paths.iter().find(|path| file_name_matches(path, &add_re));
+  let sub_path = paths.iter().find(|path| file_name_matches(path, &sub_re));
+
+  // Get the extensions of our templates. We can safely unwrap the file names
+  // because part of matching a regex means we already checked that a file
+  // name exists.
+  let extension = |path: &PathBuf, re: &Regex| re.replace_all(path.file_name().unwrap().to_str().unwrap(), "$1");
+
+  let paired_ext = match (add_path, sub_path) {
+    (Some(add_path), Some(sub_path)) => {
+      let add_ext = extension(add_path, &add_re);
+      let sub_ext = extension(sub_path, &sub_re);
+      // If the extensions are not equal, there is an error.
+      if add_ext != sub_ext {
+        return Err(error!("Template extensions for add ('{}') and sub ('{}') do not match.", add_ext, sub_ext))
+      }
+      Some(add_ext)
+    },
+    (Some(_), None) => return Err(error!("Sub template file was not found for directory '{}'.", dir.display())),
+    (None, Some(_)) => return Err(error!("Add template file was not found for directory '{}'.", dir.display())),
+    (None, None) => None,
+  };
+
+  // Find a single template with sections. It can’t be one of our add or sub
+  // templates. Named templates like `template.table.sql` look just like a
+  // single template with a longer extension, so we take the one with the
+  // same extension as our add and sub templates or else the shortest one.
+  let mut single_path: Option<&PathBuf> = None;
+  for path in paths.iter().filter(|path| file_name_matches(path, &single_re) && !file_name_matches(path, &add_re) && !file_name_matches(path, &sub_re)) {
+    let single_ext = extension(path, &single_re);
+    let better = match (&paired_ext, single_path) {
+      (&Some(ref paired_ext), _) => *paired_ext == single_ext,
+      (&None, Some(single_path)) => single_ext.len() < extension(single_path, &single_re).len(),
+      (&None, None) => true,
+    };
+    if better && try!(has_add_section(path)) { single_path = Some(path); }
   }
 
+  let single_ext = single_path.map(|path| extension(path, &single_re));
+
+  // Make sure we found a template at all.
+  let extension = match paired_ext.or(single_ext) {
+    Some(extension) => extension,
+    None => return Ok(None),
+  };
+
   // Return our template.
-  Ok(Template {
-    extension: add_ext,
-    add_path: add_path.to_path_buf(),
-    sub_path: sub_path.to_path_buf(),
-  })
+  Ok(Some(Template {
+    extension: extension,
+    add_path: add_path.cloned(),
+    sub_path: sub_path.cloned(),
+    single_path: single_path.cloned(),
+  }))
 }
 
-fn find_motions(template: &Template, dir: &Path) -> Result<Vec<Motion>, Error> {
+fn find_motions(dir: &Path, options: &FindOptions) -> Result<Vec<Motion>, Error> {
   // Get all the file names for our paths for later use. We also make sure we
   // return a tuple. This way we can keep the original path.
-  let paths = try!(find_paths(dir.to_path_buf(), 100));
+  let mut paths = try!(find_paths(dir.to_path_buf(), options));
+  // Our directory’s own motions, and those below it which don’t have a
+  // namespace of their own, belong to the namespace in its config file. If
+  // we asked for another, only directories below which declare it are ours.
+  if try!(Config::read(dir)).get("namespace").map(String::from) != options.namespace {
+    let mut kept = Vec::new();
+    for path in paths {
+      if try!(has_namespace_below(dir, &path)) { kept.push(path); }
+    }
+    paths = kept;
+  }
   let paths: Vec<(&PathBuf, &str)> = {
     paths
     .iter()
@@ -91,45 +487,300 @@ fn find_motions(template: &Template, dir: &Path) -> Result<Vec<Motion>, Error> {
     .collect()
   };
 
-  // Create the filename regexi for add and sub using the template extension.
-  let add_re = Regex::new(&(r"^(.+)\.add".to_owned() + &regex::quote(&template.extension) + "$")).unwrap();
-  let sub_re = Regex::new(&(r"^(.+)\.sub".to_owned() + &regex::quote(&template.extension) + "$")).unwrap();
+  // Every directory uses the extension of the template nearest to it, so
+  // create the file name patterns for each directory once.
+  let mut dir_patterns: BTreeMap<&Path, Patterns> = BTreeMap::new();
+  for &(path, _) in paths.iter() {
+    let parent = path.parent().unwrap();
+    if !dir_patterns.contains_key(parent) {
+      let template = try!(find_nearest_template(dir, parent, None));
+      dir_patterns.insert(parent, Patterns::new(&template.extension));
+    }
+  }
+  let patterns = |path: &Path| &dir_patterns[path.parent().unwrap()];
+
   // Construct a motions accumulator.
   let mut motions: Vec<Motion> = Vec::new();
 
   // Iterate through all of our paths…
   for &(ref add_path, add_file_name) in paths.iter() {
+    let Patterns { add: ref add_re, sub: ref sub_re, single: ref single_re, always: ref always_re } = *patterns(add_path);
     // If this path is an add file continue…
     if add_re.is_match(add_file_name) {
       // Get the name and timestamp for this motion.
       let name = add_re.replace_all(add_file_name, "$1");
-      // Skip this file if it’s name is `add_file_name`.
-      if name == "template" { continue; }
-      // Get the sub path with a name that matches our add path. If it does not
-      // exist, throw an error.
-      let &(ref sub_path, _) = try!(
+      // Skip this file if it is one of our templates.
+      if is_template_name(&name) { continue; }
+      // Get the sub path in the same directory with a name that matches our
+      // add path. If it does not exist, the motion is irreversible.
+      let sub_path = {
         paths
         .iter()
-        .find(|&&(_, sub_file_name)| sub_re.is_match(sub_file_name) && sub_re.replace_all(sub_file_name, "$1") == name)
-        .ok_or(error!("Sub file not found for add file '{}'.", add_path.display()))
-      );
+        .find(|&&(sub_path, sub_file_name)| sub_path.parent() == add_path.parent() && sub_re.is_match(sub_file_name) && sub_re.replace_all(sub_file_name, "$1") == name)
+        .map(|&(ref sub_path, _)| sub_path.to_path_buf())
+      };
       // Add the motion to our accumulator.
       motions.push(Motion {
         name: name,
         add_path: add_path.to_path_buf(),
-        sub_path: sub_path.to_path_buf(),
+        sub_path: sub_path,
+        single: false,
+        repeatable: false,
+        meta: try!(read_metadata(add_path)),
+      });
+    }
+    // Otherwise if this path is a repeatable motion continue…
+    else if always_re.is_match(add_file_name) {
+      motions.push(Motion {
+        name: always_re.replace_all(add_file_name, "$1"),
+        add_path: add_path.to_path_buf(),
+        sub_path: None,
+        single: false,
+        repeatable: true,
+        meta: try!(read_metadata(add_path)),
+      });
+    }
+    // Otherwise if this path is a single file with sections continue…
+    else if single_re.as_ref().map_or(false, |single_re| single_re.is_match(add_file_name)) && !sub_re.is_match(add_file_name) {
+      let name = single_re.as_ref().unwrap().replace_all(add_file_name, "$1");
+      // Skip our single templates.
+      if is_template_name(&name) { continue; }
+      // Only files with an add section are motions, anything else is left
+      // alone. Without a sub section the motion is irreversible.
+      let source = try!(Source::read(add_path));
+      if find_section(&source.text, "add").is_none() { continue; }
+      let has_sub = find_section(&source.text, "sub").is_some();
+      motions.push(Motion {
+        name: name,
+        add_path: add_path.to_path_buf(),
+        sub_path: if has_sub { Some(add_path.to_path_buf()) } else { None },
+        single: true,
+        repeatable: false,
+        meta: try!(read_metadata(add_path)),
       });
     }
   }
 
+  // Every sub file must belong to a motion.
+  for &(ref sub_path, sub_file_name) in paths.iter() {
+    let sub_re = &patterns(sub_path).sub;
+    if sub_re.is_match(sub_file_name) && !is_template_name(&sub_re.replace_all(sub_file_name, "$1")) {
+      if !motions.iter().any(|motion| motion.sub_path.as_ref() == Some(sub_path)) {
+        return Err(error!("Add file not found for sub file '{}'.", sub_path.display()));
+      }
+    }
+  }
+
   // Sort our motions by *file* name.
-  motions.sort_by(|a, b| a.name.cmp(&b.name));
+  motions.sort_by(|a, b| options.order.compare(&a.name, &b.name));
+
+  // Motions are recorded by name alone, so two motions with the same name in
+  // different directories would share a record.
+  for pair in motions.windows(2) {
+    if pair[0].name == pair[1].name {
+      return Err(error!("Motion names must be unique but the '{}' motion is in both '{}' and '{}'.", pair[0].name, pair[0].add_path.display(), pair[1].add_path.display()));
+    }
+  }
+
+  // Make sure every motion comes after the motions it requires.
+  let mut motions = try!(order_motions(motions));
+  // Repeatable motions are executed after every other motion, so list them
+  // last.
+  motions.sort_by_key(|motion| motion.repeatable);
 
   // Return all of our motions.
   Ok(motions)
 }
 
-fn find_paths(path: PathBuf, recurse: u8) -> Result<Vec<PathBuf>, Error> {
+/// Orders motions so that every motion comes after the motions it requires.
+/// Motions which could go in either order keep the order they were given in.
+fn order_motions(motions: Vec<Motion>) -> Result<Vec<Motion>, Error> {
+  // Make sure that every motion we require actually exists. Repeatable
+  // motions are executed after all of the others, so only another repeatable
+  // motion may require one.
+  for motion in motions.iter() {
+    for required in motion.meta.requires.iter() {
+      match motions.iter().find(|other| &other.name == required) {
+        Some(other) if other.repeatable && !motion.repeatable => {
+          return Err(error!("The '{}' motion requires the '{}' motion which is repeatable, so it is executed after every motion which is not.", motion, other));
+        },
+        Some(_) => (),
+        None => return Err(error!("The '{}' motion requires the '{}' motion which could not be found.", motion, required)),
+      }
+    }
+  }
+
+  let mut unordered = motions;
+  let mut ordered: Vec<Motion> = Vec::new();
+
+  while !unordered.is_empty() {
+    // Find the first motion whose requirements have all been ordered.
+    let next = unordered.iter().position(|motion| {
+      motion.meta.requires.iter().all(|required| ordered.iter().any(|other| &other.name == required))
+    });
+    match next {
+      Some(index) => ordered.push(unordered.remove(index)),
+      // If there is no such motion, our requirements must have a cycle.
+      None => return Err(error!("Motions may not require each other in a cycle: {}.", find_cycle(&unordered).join(" → "))),
+    }
+  }
+
+  Ok(ordered)
+}
+
+/// Finds a cycle of requirements in motions where every motion has at least
+/// one requirement which is also in the motions.
+fn find_cycle(motions: &[Motion]) -> Vec<String> {
+  let mut path: Vec<&str> = Vec::new();
+  let mut motion = &motions[0];
+  loop {
+    // If we have come back to a motion we already visited, we have our cycle.
+    if let Some(start) = path.iter().position(|name| *name == motion.name) {
+      let mut cycle = path[start..].iter().map(|name| name.to_string()).collect::<Vec<String>>();
+      cycle.push(motion.name.clone());
+      return cycle;
+    }
+    path.push(&motion.name);
+    // Follow a requirement which is stuck in our motions.
+    motion = motion.meta.requires.iter().filter_map(|required| motions.iter().find(|other| &other.name == required)).next().unwrap();
+  }
+}
+
+fn file_name_matches(path: &Path, re: &Regex) -> bool {
+  path.file_name().and_then(OsStr::to_str).map(|file_name| re.is_match(file_name)).unwrap_or(false)
+}
+
+/// Gets the section of a file marked by a line like `-- accelerate:add` or
+/// `-- accelerate:sub`, depending on `name`. The marker may be in any kind of
+/// comment. Returns the line the section starts on along with its text.
+fn find_section(text: &str, name: &str) -> Option<(usize, String)> {
+  let marker_re = Regex::new(r"^\W*accelerate:(add|sub)\W*$").unwrap();
+  let mut section: Option<(usize, String)> = None;
+  let mut in_section = false;
+  for (i, line) in text.lines().enumerate() {
+    // Markers start a new section and end the previous one.
+    if marker_re.is_match(line) {
+      in_section = marker_re.replace_all(line, "$1") == name;
+      if in_section && section.is_none() { section = Some((i + 2, String::new())); }
+    }
+    // Lines in our section are added to our section.
+    else if in_section {
+      if let Some((_, ref mut section_text)) = section {
+        section_text.push_str(line);
+        section_text.push('\n');
+      }
+    }
+  }
+  section
+}
+
+/// Whether or not a file has an add section.
+fn has_add_section(path: &Path) -> Result<bool, Error> {
+  let source = try!(Source::read(path));
+  Ok(find_section(&source.text, "add").is_some())
+}
+
+fn read_metadata(path: &Path) -> Result<Metadata, Error> {
+  parse_metadata(&try!(Source::read(path)).text).map_err(|error| error!("Invalid header in motion file '{}': {}", path.display(), error))
+}
+
+/// A line of the header at the top of a motion file, like `-- key: value`.
+const HEADER_LINE_PATTERN: &'static str = r"^\s*(?:--|#|//)\s*([A-Za-z][\w-]*)\s*:\s*(.*?)\s*$";
+
+/// Splits the lines of the header at the top of a motion file, with their
+/// line endings, from the text after it.
+fn split_header(text: &str) -> (Vec<&str>, &str) {
+  let line_re = Regex::new(HEADER_LINE_PATTERN).unwrap();
+  let mut header = Vec::new();
+  let mut rest = text;
+  while !rest.is_empty() {
+    let end = rest.find('\n').map_or(rest.len(), |index| index + 1);
+    if !line_re.is_match(rest[..end].trim_right()) { break; }
+    header.push(&rest[..end]);
+    rest = &rest[end..];
+  }
+  (header, rest)
+}
+
+/// Parses the header at the top of a motion file. The header ends at the
+/// first line which is not a comment like `-- key: value`. Keys we don’t know
+/// about are ignored.
+fn parse_metadata(text: &str) -> Result<Metadata, Error> {
+  let line_re = Regex::new(HEADER_LINE_PATTERN).unwrap();
+  let mut meta = Metadata::default();
+  for line in text.lines() {
+    let captures = match line_re.captures(line) { Some(captures) => captures, None => break };
+    let value = captures.at(2).unwrap();
+    match captures.at(1).unwrap().to_lowercase().as_str() {
+      "description" => meta.description = Some(value.to_string()),
+      "author" => meta.author = Some(value.to_string()),
+      "ticket" => meta.ticket = Some(value.to_string()),
+      "tags" => meta.tags = split_list(value),
+      "requires" => meta.requires = split_list(value),
+      "irreversible" => meta.irreversible = try!(parse_switch(value)),
+      "transaction" => meta.transaction = Some(try!(parse_switch(value))),
+      "timeout" => meta.timeout = Some(try!(parse_duration(value))),
+      "variables" => meta.variables = Some(try!(parse_switch(value))),
+      _ => (),
+    }
+  }
+  Ok(meta)
+}
+
+fn split_list(value: &str) -> Vec<String> {
+  value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+pub fn parse_switch(value: &str) -> Result<bool, Error> {
+  match value.to_lowercase().as_str() {
+    "on" | "true" | "yes" => Ok(true),
+    "off" | "false" | "no" => Ok(false),
+    _ => Err(error!("Expected 'on' or 'off' but found '{}'.", value)),
+  }
+}
+
+/// Parses durations like `500ms`, `30s`, `5m` or `1h`. Plain numbers are
+/// seconds.
+fn parse_duration(value: &str) -> Result<Duration, Error> {
+  let duration_re = Regex::new(r"^(\d+)\s*(ms|s|m|h)?$").unwrap();
+  let captures = try!(duration_re.captures(value).ok_or(error!("Expected a duration like '30s' but found '{}'.", value)));
+  let amount = try!(captures.at(1).unwrap().parse::<u64>());
+  Ok(match captures.at(2).unwrap_or("s") {
+    "ms" => Duration::from_millis(amount),
+    "m" => Duration::from_secs(amount * 60),
+    "h" => Duration::from_secs(amount * 60 * 60),
+    _ => Duration::from_secs(amount),
+  })
+}
+
+fn read_section(path: &Path, name: &str) -> Result<Source, Error> {
+  let source = try!(Source::read(path));
+  let (line, text) = try!(find_section(&source.text, name).ok_or(error!("The {} section was not found in motion file '{}'.", name, path.display())));
+  Ok(Source {
+    path: source.path,
+    text: text,
+    line: line,
+    includes: Vec::new(),
+  })
+}
+
+/// Whether a directory between a path and the motions directory has a config
+/// file which gives it a namespace.
+fn has_namespace_below(root: &Path, path: &Path) -> Result<bool, Error> {
+  let mut dir = path.parent();
+  while let Some(current) = dir {
+    if current == root { break; }
+    if current.join(config::FILE_NAME).exists() && try!(Config::read(current)).get("namespace").is_some() { return Ok(true); }
+    dir = current.parent();
+  }
+  Ok(false)
+}
+
+fn find_paths(path: PathBuf, options: &FindOptions) -> Result<Vec<PathBuf>, Error> {
+  find_paths_ignoring(path, options.max_depth, options, &Ignore::default(), &mut Vec::new())
+}
+
+fn find_paths_ignoring(path: PathBuf, recurse: usize, options: &FindOptions, ignore: &Ignore, ancestors: &mut Vec<PathBuf>) -> Result<Vec<PathBuf>, Error> {
   // If the path is a directory let’s recursively go through every entry and
   // rerun our `discover_all` function.
   if try!(fs::metadata(&path)).is_dir() {
@@ -137,16 +788,56 @@ fn find_paths(path: PathBuf, recurse: u8) -> Result<Vec<PathBuf>, Error> {
     if recurse == 0 {
       return Ok(vec![])
     }
+    // A link back to a directory we are already inside of would have us
+    // recurse forever, so we compare real paths with the directories above us.
+    let real_path = try!(fs::canonicalize(&path));
+    if ancestors.contains(&real_path) {
+      return Err(error!("Symbolic link '{}' loops back to '{}'.", path.display(), real_path.display()));
+    }
+    // A directory below ours with its own namespace has motions of its own.
+    if !ancestors.is_empty() && path.join(config::FILE_NAME).exists() {
+      let namespace = try!(Config::read(&path)).get("namespace").map(String::from);
+      if namespace.is_some() && namespace != options.namespace { return Ok(vec![]); }
+    }
+    // Add the patterns from this directory’s ignore file to the ones we got
+    // from our parents.
+    let ignore = try!(ignore.read(&path));
+    // Read the directory’s entries in order, so motions are always found in
+    // the same order whatever the file system.
+    let mut entry_paths = Vec::new();
+    for entry in try!(fs::read_dir(&path)) {
+      entry_paths.push(try!(entry).path());
+    }
+    entry_paths.sort();
     // Create a new paths vec.
     let mut paths: Vec<PathBuf> = Vec::new();
+    ancestors.push(real_path);
     // Loop through the directory…
-    for entry in try!(fs::read_dir(&path)) {
+    for entry_path in entry_paths {
+      // Apply our symlink policy before we look through the link.
+      let is_symlink = try!(fs::symlink_metadata(&entry_path)).file_type().is_symlink();
+      if is_symlink {
+        match options.symlinks {
+          Symlinks::Follow => (),
+          Symlinks::Skip => continue,
+          Symlinks::Error => return Err(error!("Symbolic link '{}' was found but symbolic links are not allowed.", entry_path.display())),
+        }
+      }
+      let metadata = match fs::metadata(&entry_path) {
+        Ok(metadata) => metadata,
+        Err(_) if is_symlink => return Err(error!("Symbolic link '{}' points to '{}' which does not exist.", entry_path.display(), try!(fs::read_link(&entry_path)).display())),
+        Err(error) => return Err(Box::new(error)),
+      };
+      // Skip anything we were asked to ignore. We don’t even look inside of
+      // ignored directories.
+      if ignore.is_ignored(&entry_path, metadata.is_dir()) { continue; }
       // Get all the paths from this entry path by recursively calling the
       // function.
-      let mut next_paths = try!(find_paths(try!(entry).path(), recurse - 1));
+      let mut next_paths = try!(find_paths_ignoring(entry_path, recurse - 1, options, &ignore, ancestors));
       // Append these next paths to our top level motions vec.
       paths.append(&mut next_paths);
     }
+    ancestors.pop();
     // Return all of our motions.
     Ok(paths)
   }
@@ -158,68 +849,352 @@ fn find_paths(path: PathBuf, recurse: u8) -> Result<Vec<PathBuf>, Error> {
 }
 
 // TODO: Tests.
-pub fn create(dir: &Path, name: &str) -> Result<(), Error> {
-  // Find the template for the directory we are creating a motion for.
-  let template = try!(find_template(dir));
-  // Add a timestamp to the name of the new motion.
-  let name = add_timestamp_to_name(name);
-  // Construct the add path and the sub path using our name and the template’s
-  // extension in addition to the directory the motions are in.
-  let mut add_path = PathBuf::new();
-  let mut sub_path = PathBuf::new();
-  add_path.push(&dir);
-  add_path.push(format!("{}.add{}", name, template.extension));
-  sub_path.push(&dir);
-  sub_path.push(format!("{}.sub{}", name, template.extension));
-  // Copy the add file and the sub file to their new locations and log some
-  // pretty things.
-  try!(copy_file(&template.add_path, &add_path));
+pub fn create(dir: &Path, name: &str, single: bool, template_name: Option<&str>, naming: Naming, from: Option<&Path>) -> Result<(), Error> {
+  // Find the template nearest to the directory we are creating a motion in.
+  let template = try!(find_nearest_template(dir, dir.join(name).parent().unwrap(), template_name));
+  // Get the time in seconds and the timestamp in minutes.
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+  let timestamp = now / 60;
+  // Get the values for the placeholders in our template.
+  let placeholders = vec![
+    ("name", name.split('/').last().unwrap().to_string()),
+    ("timestamp", timestamp.to_string()),
+    ("author", env::var("ACCELERATE_AUTHOR").or(env::var("USER")).or(env::var("USERNAME")).unwrap_or(String::new())),
+    ("date", format_date(timestamp * 60)),
+  ];
+  // Add a prefix to the name of the new motion.
+  let name = match naming {
+    Naming::Minutes => add_prefix_to_name(name, &timestamp.to_string()),
+    Naming::Seconds => add_prefix_to_name(name, &now.to_string()),
+    Naming::DateTime => add_prefix_to_name(name, &format_datetime(now)),
+    Naming::Sequential => try!(sequential_name(dir, name)),
+  };
+  // Instead of using the template we may copy an existing add file, and
+  // generate a sub file which undoes it.
+  let from_contents = match from {
+    Some(from) => {
+      // Includes are found relative to the file which includes them, which
+      // our new motion may not be next to, so we copy the text they include.
+      let add_contents = try!(try!(Source::read(from)).read_includes()).full_text();
+      let sub_contents = invert::invert(&add_contents);
+      Some((add_contents, sub_contents))
+    },
+    None => None,
+  };
+  if single {
+    if template.extension.is_empty() {
+      return Err(error!("Single file motions need a template with an extension, like `template.sql`, to tell them apart from other files."));
+    }
+    // Construct the path of our single file using our name and the template’s
+    // extension in addition to the directory the motions are in.
+    let mut path = PathBuf::new();
+    path.push(&dir);
+    path.push(format!("{}{}", name, template.extension));
+    // Use the single template if we have one, otherwise put our add and sub
+    // templates into sections.
+    let contents = match (from_contents, template.single_path) {
+      (Some((add_contents, sub_contents)), _) => sections(&add_contents, &sub_contents),
+      (None, Some(ref single_path)) => expand_placeholders(&try!(Source::read(single_path)).text, &placeholders),
+      (None, None) => expand_placeholders(&sections(
+        &try!(Source::read(template.add_path.as_ref().unwrap())).text,
+        &try!(Source::read(template.sub_path.as_ref().unwrap())).text
+      ), &placeholders),
+    };
+    try!(write_file(&path, &contents));
+    println!("{} {}", "Create".green().bold(), path.display());
+  } else {
+    // Construct the add path and the sub path using our name and the template’s
+    // extension in addition to the directory the motions are in.
+    let mut add_path = PathBuf::new();
+    let mut sub_path = PathBuf::new();
+    add_path.push(&dir);
+    add_path.push(format!("{}.add{}", name, template.extension));
+    sub_path.push(&dir);
+    sub_path.push(format!("{}.sub{}", name, template.extension));
+    // Use the add and sub templates if we have them, otherwise split the
+    // sections of our single template.
+    let (add_contents, sub_contents) = match (from_contents, template.add_path, template.sub_path) {
+      (Some(contents), _, _) => contents,
+      (None, Some(add_path), Some(sub_path)) => (
+        expand_placeholders(&try!(Source::read(&add_path)).text, &placeholders),
+        expand_placeholders(&try!(Source::read(&sub_path)).text, &placeholders)
+      ),
+      _ => {
+        let single_path = template.single_path.unwrap();
+        (
+          expand_placeholders(&try!(read_section(&single_path, "add")).text, &placeholders),
+          expand_placeholders(&try!(read_section(&single_path, "sub")).text, &placeholders)
+        )
+      },
+    };
+    // Never overwrite a motion, not even half of one.
+    if let Some(path) = [&add_path, &sub_path].iter().find(|path| path.exists()) {
+      return Err(error!("The motion file '{}' already exists.", path.display()));
+    }
+    // Write the add file and the sub file to their new locations and log some
+    // pretty things.
+    try!(write_file(&add_path, &add_contents));
+    println!("{} {}", "Create".green().bold(), add_path.display());
+    try!(write_file(&sub_path, &sub_contents));
+    println!("{} {}", "Create".green().bold(), sub_path.display());
+  }
+  Ok(())
+}
+
+/// Writes a single file with an add section and a sub section.
+fn sections(add_contents: &str, sub_contents: &str) -> String {
+  let mut contents = "-- accelerate:add\n".to_owned();
+  push_part(&mut contents, add_contents);
+  push_part(&mut contents, "-- accelerate:sub\n");
+  push_part(&mut contents, sub_contents);
+  contents
+}
+
+/// Writes a sub file for a motion which doesn’t have one, generated from its
+/// add file. A single file motion gets a sub section instead.
+pub fn generate_sub(motion: &Motion) -> Result<PathBuf, Error> {
+  if motion.repeatable { return Err(error!("The '{}' motion is repeatable so it is never subbed.", motion)); }
+  if motion.sub_path.is_some() { return Err(error!("The '{}' motion can already be subbed.", motion)); }
+  let sub_contents = invert::invert(&try!(motion.read_add()).full_text());
+  if motion.single {
+    let mut contents = try!(Source::read(&motion.add_path)).text;
+    push_part(&mut contents, "-- accelerate:sub\n");
+    push_part(&mut contents, &sub_contents);
+    // Write next to the motion and move it into place, so a failed write
+    // never leaves the motion half written.
+    let file_name = motion.add_path.file_name().unwrap().to_string_lossy().into_owned();
+    let temp_path = motion.add_path.with_file_name(format!(".{}.tmp", file_name));
+    {
+      let mut file = try!(File::create(&temp_path));
+      try!(file.write_all(contents.as_bytes()));
+      try!(file.sync_all());
+    }
+    if let Err(error) = fs::rename(&temp_path, &motion.add_path) {
+      let _ = fs::remove_file(&temp_path);
+      return Err(Box::new(error));
+    }
+    Ok(motion.add_path.clone())
+  } else {
+    // Sub files are named like their add file with `.sub` for `.add`.
+    let file_name = motion.add_path.file_name().unwrap().to_string_lossy().into_owned();
+    let sub_path = motion.add_path.with_file_name(format!("{}.sub{}", motion.name, &file_name[motion.name.len() + ".add".len()..]));
+    try!(write_file(&sub_path, &sub_contents));
+    Ok(sub_path)
+  }
+}
+
+/// The directory squashed motions are moved into, below the motions
+/// directory.
+pub const ARCHIVE_DIR: &'static str = "archive";
+
+/// Squashes motions into a single new motion in the motions directory which
+/// adds all of them in order and subs them in reverse. The new motion is only
+/// reversible if all of them were. The squashed motions are moved into the
+/// archive directory, which discovery is told to ignore.
+pub fn squash(dir: &Path, motions: &[Motion], name: &str) -> Result<Motion, Error> {
+  let template = try!(find_nearest_template(dir, dir, None));
+  let add_path = dir.join(format!("{}.add{}", name, template.extension));
+  let sub_path = dir.join(format!("{}.sub{}", name, template.extension));
+  // The squashed motion is executed all at once, so the motions in it must
+  // agree on how they are executed.
+  if let Some(motion) = motions.iter().find(|motion| {
+    motion.meta.transaction != motions[0].meta.transaction || motion.meta.timeout != motions[0].meta.timeout || motion.meta.variables != motions[0].meta.variables
+  }) {
+    return Err(error!("The '{}' motion has a different `transaction`, `timeout` or `variables` header than the '{}' motion, so they can not be squashed together.", motion, motions[0]));
+  }
+  // Read everything before we write anything. The headers of the motions we
+  // squash describe them and not the squashed motion, so they are left out
+  // except for the lines about how it is executed.
+  let mut add_text = String::new();
+  if let Some(motion) = motions.first() {
+    let execution_re = Regex::new(r"^\s*(?:--|#|//)\s*(?i:transaction|timeout|variables)\s*:").unwrap();
+    let first_text = try!(Source::read(&motion.add_path)).text;
+    for line in split_header(&first_text).0.into_iter().filter(|line| execution_re.is_match(line)) {
+      add_text.push_str(line);
+    }
+  }
+  for motion in motions.iter() {
+    push_part(&mut add_text, split_header(&try!(motion.read_add()).full_text()).1);
+  }
+  let sub_text = if motions.iter().any(Motion::is_irreversible) {
+    None
+  } else {
+    let mut sub_text = String::new();
+    for motion in motions.iter().rev() {
+      push_part(&mut sub_text, split_header(&try!(motion.read_sub()).full_text()).1);
+    }
+    Some(sub_text)
+  };
+  // Make sure every file can be archived before we move any of them. The
+  // directories they were in are kept.
+  let archive_dir = dir.join(ARCHIVE_DIR);
+  let mut moves = Vec::new();
+  for motion in motions.iter() {
+    let mut paths = vec![&motion.add_path];
+    if let Some(ref sub_path) = motion.sub_path {
+      if *sub_path != motion.add_path { paths.push(sub_path); }
+    }
+    let mut motion_moves = Vec::new();
+    for path in paths {
+      let archive_path = archive_dir.join(try!(path.strip_prefix(dir)));
+      if archive_path.exists() { return Err(error!("The archived file '{}' already exists.", archive_path.display())); }
+      motion_moves.push((path, archive_path));
+    }
+    moves.push((motion, motion_moves));
+  }
+  // Write our new motion.
+  try!(write_file(&add_path, &add_text));
   println!("{} {}", "Create".green().bold(), add_path.display());
-  try!(copy_file(&template.sub_path, &sub_path));
-  println!("{} {}", "Create".green().bold(), sub_path.display());
+  if let Some(ref sub_text) = sub_text {
+    try!(write_file(&sub_path, sub_text));
+    println!("{} {}", "Create".green().bold(), sub_path.display());
+  }
+  // Move the motions we squashed into the archive.
+  for (motion, motion_moves) in moves {
+    for (path, archive_path) in motion_moves {
+      try!(fs::create_dir_all(archive_path.parent().unwrap()));
+      try!(fs::rename(path, &archive_path));
+    }
+    println!("{} {}", "Archive".yellow().bold(), motion);
+  }
+  try!(ignore_archive(dir));
+  Ok(Motion {
+    name: name.to_string(),
+    add_path: add_path.clone(),
+    sub_path: if sub_text.is_some() { Some(sub_path) } else { None },
+    meta: try!(read_metadata(&add_path)),
+    ..Motion::default()
+  })
+}
+
+/// Adds one part to the text of a squashed motion, making sure each part
+/// starts on a line of its own.
+fn push_part(text: &mut String, part: &str) {
+  if !text.is_empty() && !text.ends_with("\n") { text.push('\n'); }
+  text.push_str(part);
+}
+
+/// Adds the archive directory to the ignore file of the motions directory, if
+/// it isn’t there already.
+fn ignore_archive(dir: &Path) -> Result<(), Error> {
+  let path = dir.join(ignore::FILE_NAME);
+  let line = format!("/{}/", ARCHIVE_DIR);
+  let mut text = String::new();
+  if path.exists() { try!(try!(File::open(&path)).read_to_string(&mut text)); }
+  if text.lines().any(|existing| existing.trim() == line) { return Ok(()); }
+  let mut file = try!(OpenOptions::new().create(true).append(true).open(&path));
+  if !text.is_empty() && !text.ends_with("\n") { try!(file.write_all(b"\n")); }
+  try!(writeln!(file, "{}", line));
   Ok(())
 }
 
-fn add_timestamp_to_name(name: &str) -> String {
+fn add_prefix_to_name(name: &str, prefix: &str) -> String {
   // Get all of the name’s segments (path segments).
   let mut segments = name.split('/').map(String::from).collect::<Vec<String>>();
   // Get the index of the last segment.
   let last_i = segments.len() - 1;
-  // Get the timestamp in minutes.
-  let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().checked_div(60).unwrap();
-  // Add the timestamp to the last segment.
-  segments[last_i] = format!("{}", timestamp) + "-" + &segments[last_i];
+  // Add the prefix to the last segment.
+  segments[last_i] = prefix.to_owned() + "-" + &segments[last_i];
   // Rejoin the segments with a slash and return the new name.
   segments.join("/")
 }
 
-fn copy_file(in_path: &Path, out_path: &Path) -> Result<(), Error> {
-  // Open the file we will copy.
-  let mut in_file = try!(File::open(in_path));
-  // Read the contents of our in file to a string.
-  let mut contents = String::new();
-  try!(in_file.read_to_string(&mut contents));
-  // Create the directory for our out path.
-  if let Some(parent) = out_path.parent() { try!(fs::create_dir_all(parent)); }
-  // Create our out file.
-  let mut out_file = try!(File::create(out_path));
-  // Write all of the contents of our in file to the out file.
-  try!(out_file.write_all(contents.as_bytes()));
+/// Numbers a new motion one higher than the highest numbered motion anywhere
+/// in the motions directory, padded with zeros to the same width. A name which
+/// already has a number keeps it, as long as it is higher than the rest.
+fn sequential_name(dir: &Path, name: &str) -> Result<String, Error> {
+  let number_re = Regex::new(r"^(\d+)-").unwrap();
+  let number = |file_name: &str| number_re.captures(file_name).map(|captures| captures.at(1).unwrap().to_string());
+  // Find the highest number, along with how many digits it was written with.
+  let mut highest: Option<(u64, usize)> = None;
+  for path in try!(find_paths(dir.to_path_buf(), &FindOptions::default())) {
+    if let Some(digits) = path.file_name().and_then(OsStr::to_str).and_then(&number) {
+      let value = try!(digits.parse::<u64>());
+      if highest.map_or(true, |(highest, _)| value > highest) { highest = Some((value, digits.len())); }
+    }
+  }
+  match number(name.split('/').last().unwrap()) {
+    Some(digits) => {
+      let value = try!(digits.parse::<u64>());
+      match highest {
+        Some((highest, _)) if value <= highest => Err(error!("The motion number {} is not higher than {}, the highest motion number so far.", digits, highest)),
+        _ => Ok(name.to_string()),
+      }
+    },
+    None => {
+      let (next, width) = highest.map(|(highest, width)| (highest + 1, width)).unwrap_or((1, 4));
+      Ok(add_prefix_to_name(name, &format!("{:0width$}", next, width = width)))
+    },
+  }
+}
+
+/// Replaces placeholders like `{{name}}` in a template. Placeholders we don’t
+/// know about are left alone.
+fn expand_placeholders(text: &str, placeholders: &[(&str, String)]) -> String {
+  let placeholder_re = Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap();
+  placeholder_re.replace_all(text, |captures: &regex::Captures| {
+    let name = captures.at(1).unwrap();
+    match placeholders.iter().find(|&&(placeholder, _)| placeholder == name) {
+      Some(&(_, ref value)) => value.clone(),
+      None => captures.at(0).unwrap().to_string(),
+    }
+  })
+}
+
+/// Formats the UTC date of a Unix timestamp in seconds like `2016-07-04`.
+fn format_date(timestamp: u64) -> String {
+  // Convert days since the epoch to a civil date, see
+  // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+  let days = (timestamp / 86400) as i64 + 719468;
+  let era = days / 146097;
+  let day_of_era = days - era * 146097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+  let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats a Unix timestamp in seconds as a UTC date and time like
+/// `20160704123000`.
+fn format_datetime(timestamp: u64) -> String {
+  let seconds = timestamp % 86400;
+  format!("{}{:02}{:02}{:02}", format_date(timestamp).replace("-", ""), seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), Error> {
+  // Create the directory for our path.
+  if let Some(parent) = path.parent() { try!(fs::create_dir_all(parent)); }
+  // Create our file, unless there already is one.
+  let mut file = try!(OpenOptions::new().write(true).create_new(true).open(path).map_err(|error| {
+    if error.kind() == ErrorKind::AlreadyExists { error!("The motion file '{}' already exists.", path.display()) } else { Box::new(error) as Error }
+  }));
+  // Write all of the contents to the file.
+  try!(file.write_all(contents.as_bytes()));
   Ok(())
 }
 
 #[cfg(test)]
 mod tests {
   use std::path::{Path, PathBuf};
-  use super::{find_paths, find, Motion, find_template, Template};
+  use source::Source;
+  use std::time::Duration;
+  use checksum::checksum;
+  use super::{find_paths, find, FindOptions, Symlinks, Motion, Metadata, find_template, find_nearest_template, Template, expand_placeholders, format_date, format_datetime, sequential_name, write_file, Naming, Order, find_section, parse_metadata, split_header, order_motions, chunks};
 
   fn pb(path: &str) -> PathBuf {
     Path::new(path).to_path_buf()
   }
 
+  fn depth(max_depth: usize) -> FindOptions {
+    FindOptions { max_depth: max_depth, ..FindOptions::default() }
+  }
+
   #[test]
   fn test_find_paths() {
-    assert_eq!(find_paths(pb("tests/fixtures/nested"), 100).unwrap(), vec![
+    assert_eq!(find_paths(pb("tests/fixtures/nested"), &depth(100)).unwrap(), vec![
       pb("tests/fixtures/nested/234567-bar.add"),
       pb("tests/fixtures/nested/234567-bar.sub"),
       pb("tests/fixtures/nested/a/345678-baz.add"),
@@ -235,7 +1210,7 @@ mod tests {
 
   #[test]
   fn test_find_paths_limit_recurse_1() {
-    assert_eq!(find_paths(pb("tests/fixtures/nested"), 1).unwrap(), vec![
+    assert_eq!(find_paths(pb("tests/fixtures/nested"), &depth(1)).unwrap(), vec![
       pb("tests/fixtures/nested/234567-bar.add"),
       pb("tests/fixtures/nested/234567-bar.sub"),
       pb("tests/fixtures/nested/template.add"),
@@ -245,7 +1220,7 @@ mod tests {
 
   #[test]
   fn test_find_paths_limit_recurse_2() {
-    assert_eq!(find_paths(pb("tests/fixtures/nested"), 2).unwrap(), vec![
+    assert_eq!(find_paths(pb("tests/fixtures/nested"), &depth(2)).unwrap(), vec![
       pb("tests/fixtures/nested/234567-bar.add"),
       pb("tests/fixtures/nested/234567-bar.sub"),
       pb("tests/fixtures/nested/a/345678-baz.add"),
@@ -257,99 +1232,480 @@ mod tests {
     ]);
   }
 
+  #[cfg(unix)]
+  #[test]
+  fn test_find_paths_symlinks() {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    // Build a motions directory which links to a shared module and, inside of
+    // that module, back to the motions directory.
+    let dir = env::temp_dir().join("accelerate-test-find-paths-symlinks");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("motions")).unwrap();
+    fs::create_dir_all(dir.join("shared")).unwrap();
+    fs::File::create(dir.join("motions/123456-foo.add")).unwrap();
+    fs::File::create(dir.join("shared/234567-bar.add")).unwrap();
+    symlink(dir.join("shared"), dir.join("motions/shared")).unwrap();
+    let follow = find_paths(dir.join("motions"), &FindOptions::default()).unwrap();
+    assert_eq!(follow, vec![dir.join("motions/123456-foo.add"), dir.join("motions/shared/234567-bar.add")]);
+    let skip = find_paths(dir.join("motions"), &FindOptions { symlinks: Symlinks::Skip, ..FindOptions::default() }).unwrap();
+    assert_eq!(skip, vec![dir.join("motions/123456-foo.add")]);
+    assert!(find_paths(dir.join("motions"), &FindOptions { symlinks: Symlinks::Error, ..FindOptions::default() }).is_err());
+    symlink(dir.join("motions"), dir.join("shared/loop")).unwrap();
+    let error = find_paths(dir.join("motions"), &FindOptions::default()).unwrap_err();
+    assert!(format!("{}", error).starts_with(&format!("Symbolic link '{}' loops back", dir.join("motions/shared/loop").display())));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_symlinks_from_str() {
+    assert_eq!("follow".parse::<Symlinks>().unwrap(), Symlinks::Follow);
+    assert_eq!("skip".parse::<Symlinks>().unwrap(), Symlinks::Skip);
+    assert_eq!("error".parse::<Symlinks>().unwrap(), Symlinks::Error);
+    assert!("sometimes".parse::<Symlinks>().is_err());
+  }
+
   #[test]
   fn test_fixtures_basic() {
-    assert_eq!(find(Path::new("tests/fixtures/basic")).unwrap(), vec![
+    assert_eq!(find(Path::new("tests/fixtures/basic"), &FindOptions::default()).unwrap(), vec![
       Motion {
         name: "123456-foo".to_string(),
         add_path: pb("tests/fixtures/basic/123456-foo.add"),
-        sub_path: pb("tests/fixtures/basic/123456-foo.sub"),
+        sub_path: Some(pb("tests/fixtures/basic/123456-foo.sub")),
+        ..Motion::default()
       },
       Motion {
         name: "234567-bar".to_string(),
         add_path: pb("tests/fixtures/basic/234567-bar.add"),
-        sub_path: pb("tests/fixtures/basic/234567-bar.sub"),
+        sub_path: Some(pb("tests/fixtures/basic/234567-bar.sub")),
+        ..Motion::default()
       },
     ]);
   }
 
   #[test]
   fn test_template_basic() {
-    assert_eq!(find_template(Path::new("tests/fixtures/basic")).unwrap(), Template {
+    assert_eq!(find_template(Path::new("tests/fixtures/basic"), None).unwrap(), Some(Template {
       extension: "".to_string(),
-      add_path: pb("tests/fixtures/basic/template.add"),
-      sub_path: pb("tests/fixtures/basic/template.sub"),
-    });
+      add_path: Some(pb("tests/fixtures/basic/template.add")),
+      sub_path: Some(pb("tests/fixtures/basic/template.sub")),
+      single_path: None,
+    }));
   }
 
   #[test]
   fn test_fixtures_nested() {
-    assert_eq!(find(Path::new("tests/fixtures/nested")).unwrap(), vec![
+    assert_eq!(find(Path::new("tests/fixtures/nested"), &FindOptions::default()).unwrap(), vec![
       Motion {
         name: "123456-foo".to_string(),
         add_path: pb("tests/fixtures/nested/b/123456-foo.add"),
-        sub_path: pb("tests/fixtures/nested/b/123456-foo.sub"),
+        sub_path: Some(pb("tests/fixtures/nested/b/123456-foo.sub")),
+        ..Motion::default()
       },
       Motion {
         name: "234567-bar".to_string(),
         add_path: pb("tests/fixtures/nested/234567-bar.add"),
-        sub_path: pb("tests/fixtures/nested/234567-bar.sub"),
+        sub_path: Some(pb("tests/fixtures/nested/234567-bar.sub")),
+        ..Motion::default()
       },
       Motion {
         name: "345678-baz".to_string(),
         add_path: pb("tests/fixtures/nested/a/345678-baz.add"),
-        sub_path: pb("tests/fixtures/nested/a/345678-baz.sub"),
+        sub_path: Some(pb("tests/fixtures/nested/a/345678-baz.sub")),
+        ..Motion::default()
       },
       Motion {
         name: "456789-qux".to_string(),
         add_path: pb("tests/fixtures/nested/b/c/456789-qux.add"),
-        sub_path: pb("tests/fixtures/nested/b/c/456789-qux.sub"),
+        sub_path: Some(pb("tests/fixtures/nested/b/c/456789-qux.sub")),
+        ..Motion::default()
       },
     ]);
   }
 
   #[test]
   fn test_template_nested() {
-    assert_eq!(find_template(Path::new("tests/fixtures/nested")).unwrap(), Template {
+    assert_eq!(find_template(Path::new("tests/fixtures/nested"), None).unwrap(), Some(Template {
       extension: "".to_string(),
-      add_path: pb("tests/fixtures/nested/template.add"),
-      sub_path: pb("tests/fixtures/nested/template.sub"),
-    });
+      add_path: Some(pb("tests/fixtures/nested/template.add")),
+      sub_path: Some(pb("tests/fixtures/nested/template.sub")),
+      single_path: None,
+    }));
   }
 
   #[test]
   fn test_fixtures_extension() {
-    assert_eq!(find(Path::new("tests/fixtures/extension")).unwrap(), vec![
+    assert_eq!(find(Path::new("tests/fixtures/extension"), &FindOptions::default()).unwrap(), vec![
       Motion {
         name: "123456-foo".to_string(),
         add_path: pb("tests/fixtures/extension/123456-foo.add.sql"),
-        sub_path: pb("tests/fixtures/extension/123456-foo.sub.sql"),
+        sub_path: Some(pb("tests/fixtures/extension/123456-foo.sub.sql")),
+        ..Motion::default()
       },
       Motion {
         name: "234567-bar".to_string(),
         add_path: pb("tests/fixtures/extension/234567-bar.add.sql"),
-        sub_path: pb("tests/fixtures/extension/234567-bar.sub.sql"),
+        sub_path: Some(pb("tests/fixtures/extension/234567-bar.sub.sql")),
+        ..Motion::default()
       },
     ]);
   }
 
   #[test]
   fn test_template_extension() {
-    assert_eq!(find_template(Path::new("tests/fixtures/extension")).unwrap(), Template {
+    assert_eq!(find_template(Path::new("tests/fixtures/extension"), None).unwrap(), Some(Template {
+      extension: ".sql".to_string(),
+      add_path: Some(pb("tests/fixtures/extension/template.add.sql")),
+      sub_path: Some(pb("tests/fixtures/extension/template.sub.sql")),
+      single_path: None,
+    }));
+  }
+
+  #[test]
+  fn test_fixtures_single() {
+    assert_eq!(find(Path::new("tests/fixtures/single"), &FindOptions::default()).unwrap(), vec![
+      Motion {
+        name: "123456-foo".to_string(),
+        add_path: pb("tests/fixtures/single/123456-foo.sql"),
+        sub_path: Some(pb("tests/fixtures/single/123456-foo.sql")),
+        single: true,
+        ..Motion::default()
+      },
+      Motion {
+        name: "234567-bar".to_string(),
+        add_path: pb("tests/fixtures/single/234567-bar.add.sql"),
+        sub_path: Some(pb("tests/fixtures/single/234567-bar.sub.sql")),
+        ..Motion::default()
+      },
+    ]);
+  }
+
+  #[test]
+  fn test_template_single() {
+    assert_eq!(find_template(Path::new("tests/fixtures/single"), None).unwrap(), Some(Template {
       extension: ".sql".to_string(),
-      add_path: pb("tests/fixtures/extension/template.add.sql"),
-      sub_path: pb("tests/fixtures/extension/template.sub.sql"),
+      add_path: None,
+      sub_path: None,
+      single_path: Some(pb("tests/fixtures/single/template.sql")),
+    }));
+  }
+
+  #[test]
+  fn test_fixtures_mixed() {
+    let motions = find(Path::new("tests/fixtures/mixed"), &FindOptions::default()).unwrap();
+    assert_eq!(motions.iter().map(|motion| motion.add_path.clone()).collect::<Vec<_>>(), vec![
+      pb("tests/fixtures/mixed/123456-foo.add.sql"),
+      pb("tests/fixtures/mixed/billing/234567-invoices.add.sql"),
+      pb("tests/fixtures/mixed/search/345678-index.add.json"),
+    ]);
+  }
+
+  #[test]
+  fn test_find_nearest_template() {
+    let root = Path::new("tests/fixtures/mixed");
+    assert_eq!(find_nearest_template(root, root, None).unwrap().extension, ".sql");
+    assert_eq!(find_nearest_template(root, &root.join("billing"), None).unwrap().extension, ".sql");
+    assert_eq!(find_nearest_template(root, &root.join("search"), None).unwrap().extension, ".json");
+    assert_eq!(find_nearest_template(root, &root.join("search/new"), None).unwrap().extension, ".json");
+    assert!(find_nearest_template(Path::new("tests/fixtures/bad/templateless"), Path::new("tests/fixtures/bad/templateless"), None).is_err());
+  }
+
+  #[test]
+  fn test_fixtures_templates() {
+    assert_eq!(find(Path::new("tests/fixtures/templates"), &FindOptions::default()).unwrap().iter().map(|motion| motion.name.clone()).collect::<Vec<_>>(), vec!["123456-foo"]);
+  }
+
+  #[test]
+  fn test_template_named() {
+    let dir = Path::new("tests/fixtures/templates");
+    assert_eq!(find_template(dir, None).unwrap(), Some(Template {
+      extension: ".sql".to_string(),
+      add_path: Some(pb("tests/fixtures/templates/template.add.sql")),
+      sub_path: Some(pb("tests/fixtures/templates/template.sub.sql")),
+      single_path: None,
+    }));
+    assert_eq!(find_template(dir, Some("table")).unwrap(), Some(Template {
+      extension: ".sql".to_string(),
+      add_path: Some(pb("tests/fixtures/templates/template.table.add.sql")),
+      sub_path: Some(pb("tests/fixtures/templates/template.table.sub.sql")),
+      single_path: None,
+    }));
+    assert_eq!(find_template(dir, Some("data")).unwrap(), Some(Template {
+      extension: ".sql".to_string(),
+      add_path: None,
+      sub_path: None,
+      single_path: Some(pb("tests/fixtures/templates/template.data.sql")),
+    }));
+    assert_eq!(find_template(dir, Some("nope")).unwrap(), None);
+  }
+
+  #[test]
+  fn test_expand_placeholders() {
+    let placeholders = vec![("name", "invoices".to_string()), ("author", "Jane".to_string())];
+    assert_eq!(expand_placeholders("create table {{name}} ();\n-- {{ author }} {{nope}}", &placeholders), "create table invoices ();\n-- Jane {{nope}}");
+  }
+
+  #[test]
+  fn test_format_datetime() {
+    assert_eq!(format_datetime(0), "19700101000000");
+    assert_eq!(format_datetime(1467635405), "20160704123005");
+  }
+
+  #[test]
+  fn test_sequential_name() {
+    assert_eq!(sequential_name(Path::new("tests/fixtures/basic"), "baz").unwrap(), "234568-baz");
+    assert_eq!(sequential_name(Path::new("tests/fixtures/nested"), "a/quux").unwrap(), "a/456790-quux");
+    assert_eq!(sequential_name(Path::new("tests/fixtures/basic"), "999999-baz").unwrap(), "999999-baz");
+    assert!(sequential_name(Path::new("tests/fixtures/basic"), "200000-baz").is_err());
+    assert_eq!(sequential_name(Path::new("tests/fixtures/bad/templateless"), "baz").unwrap(), "123457-baz");
+  }
+
+  #[test]
+  fn test_order_compare() {
+    use std::cmp::Ordering::*;
+    assert_eq!(Order::Natural.compare("9-init", "10-users"), Less);
+    assert_eq!(Order::Lexical.compare("9-init", "10-users"), Greater);
+    assert_eq!(Order::Natural.compare("123456-foo", "123456-foo"), Equal);
+    assert_eq!(Order::Natural.compare("123456-foo", "123456-foo2"), Less);
+    assert_eq!(Order::Natural.compare("a/2-x", "a/10-x"), Less);
+    assert_eq!(Order::Natural.compare("010-x", "10-x"), Less);
+    assert_eq!(Order::Natural.compare("99999999999999999999999-x", "100000000000000000000000-x"), Less);
+    assert_eq!(Order::Natural.compare("10-b", "10-a"), Greater);
+  }
+
+  #[test]
+  fn test_chunks() {
+    assert_eq!(chunks("a/10-é2"), vec!["a/", "10", "-é", "2"]);
+    assert_eq!(chunks("123"), vec!["123"]);
+    assert!(chunks("").is_empty());
+  }
+
+  #[test]
+  fn test_fixtures_natural() {
+    let names = |order| find(Path::new("tests/fixtures/natural"), &FindOptions { order: order, ..FindOptions::default() }).unwrap().into_iter().map(|motion| motion.name).collect::<Vec<_>>();
+    assert_eq!(names(Order::Natural), vec!["9-init", "10-users"]);
+    assert_eq!(names(Order::Lexical), vec!["10-users", "9-init"]);
+  }
+
+  #[test]
+  fn test_naming_from_str() {
+    assert_eq!("datetime".parse::<Naming>().unwrap(), Naming::DateTime);
+    assert!("hours".parse::<Naming>().is_err());
+  }
+
+  #[test]
+  fn test_write_file_existing() {
+    assert!(write_file(Path::new("tests/fixtures/basic/123456-foo.add"), "nope\n").is_err());
+    assert_eq!(Source::read(Path::new("tests/fixtures/basic/123456-foo.add")).unwrap().text, "foo+\n");
+  }
+
+  #[test]
+  fn test_format_date() {
+    assert_eq!(format_date(0), "1970-01-01");
+    assert_eq!(format_date(951782400), "2000-02-29");
+    assert_eq!(format_date(1467590400), "2016-07-04");
+  }
+
+  #[test]
+  fn test_motion_checksum_include() {
+    let motions = find(Path::new("tests/fixtures/include"), &FindOptions::default()).unwrap();
+    assert_eq!(motions[0].checksum().unwrap(), checksum("create table foo ();\ncreate trigger audit;\n\ndrop table foo;\n"));
+  }
+
+  #[test]
+  fn test_find_section() {
+    let text = "-- accelerate:add\nfoo+\n\n-- accelerate:sub\nfoo-\n";
+    assert_eq!(find_section(text, "add"), Some((2, "foo+\n\n".to_string())));
+    assert_eq!(find_section(text, "sub"), Some((5, "foo-\n".to_string())));
+    assert_eq!(find_section("# accelerate:add\n// accelerate:sub\nfoo-", "sub"), Some((3, "foo-\n".to_string())));
+    assert_eq!(find_section("-- accelerate:add\nfoo+\n", "sub"), None);
+  }
+
+  #[test]
+  fn test_motion_read_single() {
+    let motion = Motion {
+      name: "345678-baz".to_string(),
+      add_path: pb("tests/fixtures/sections/345678-baz.sql"),
+      sub_path: Some(pb("tests/fixtures/sections/345678-baz.sql")),
+      single: true,
+      ..Motion::default()
+    };
+    assert_eq!(motion.read_add().unwrap(), Source {
+      path: pb("tests/fixtures/sections/345678-baz.sql"),
+      text: "baz+\n\n".to_string(),
+      line: 2,
+      includes: vec![],
+    });
+    assert_eq!(motion.read_sub().unwrap(), Source {
+      path: pb("tests/fixtures/sections/345678-baz.sql"),
+      text: "baz-\nfail\n".to_string(),
+      line: 5,
+      includes: vec![],
+    });
+  }
+
+  #[test]
+  fn test_fixtures_metadata() {
+    let motions = find(Path::new("tests/fixtures/metadata"), &FindOptions::default()).unwrap();
+    assert_eq!(motions[0].meta, Metadata {
+      description: Some("Adds foo".to_string()),
+      author: Some("Jane".to_string()),
+      ticket: Some("FOO-1".to_string()),
+      tags: vec!["billing".to_string(), "seed".to_string()],
+      requires: vec![],
+      irreversible: false,
+      transaction: Some(true),
+      timeout: Some(Duration::from_secs(30)),
+      variables: None,
+    });
+    assert_eq!(motions[1].meta, Metadata {
+      description: Some("Adds bar".to_string()),
+      ticket: Some("BAR-2".to_string()),
+      ..Metadata::default()
+    });
+  }
+
+  #[test]
+  fn test_split_header() {
+    assert_eq!(split_header("-- ticket: ABC-123\r\n# tags: seed\nselect 1;\n-- author: me\n"), (vec!["-- ticket: ABC-123\r\n", "# tags: seed\n"], "select 1;\n-- author: me\n"));
+    assert_eq!(split_header("-- ticket: ABC-123"), (vec!["-- ticket: ABC-123"], ""));
+    assert_eq!(split_header("select 1;\n"), (vec![], "select 1;\n"));
+  }
+
+  #[test]
+  fn test_parse_metadata() {
+    assert_eq!(parse_metadata("").unwrap(), Metadata::default());
+    assert_eq!(parse_metadata("create table foo ();\n-- ticket: FOO-1\n").unwrap(), Metadata::default());
+    assert_eq!(parse_metadata("# author: Jane\n// TODO: ignored\n-- timeout: 500ms\n").unwrap(), Metadata {
+      author: Some("Jane".to_string()),
+      timeout: Some(Duration::from_millis(500)),
+      ..Metadata::default()
+    });
+    assert_eq!(parse_metadata("-- accelerate:add\n-- transaction: off\n-- timeout: 2m\n").unwrap(), Metadata {
+      transaction: Some(false),
+      timeout: Some(Duration::from_secs(120)),
+      ..Metadata::default()
+    });
+    assert_eq!(parse_metadata("-- variables: on\n").unwrap(), Metadata {
+      variables: Some(true),
+      ..Metadata::default()
     });
+    assert!(parse_metadata("-- transaction: maybe\n").is_err());
+    assert!(parse_metadata("-- timeout: soon\n").is_err());
+  }
+
+  #[test]
+  fn test_metadata_summary() {
+    assert_eq!(Metadata::default().summary(), "");
+    assert_eq!(Metadata {
+      description: Some("Adds foo".to_string()),
+      author: Some("Jane".to_string()),
+      ticket: Some("FOO-1".to_string()),
+      tags: vec!["billing".to_string(), "seed".to_string()],
+      ..Metadata::default()
+    }.summary(), "Adds foo (FOO-1) by Jane [billing, seed]");
+  }
+
+  fn requiring(name: &str, requires: &[&str]) -> Motion {
+    Motion {
+      name: name.to_string(),
+      meta: Metadata {
+        requires: requires.iter().map(|name| name.to_string()).collect(),
+        ..Metadata::default()
+      },
+      ..Motion::default()
+    }
+  }
+
+  fn names(motions: Vec<Motion>) -> Vec<String> {
+    motions.into_iter().map(|motion| motion.name).collect()
+  }
+
+  #[test]
+  fn test_order_motions() {
+    assert_eq!(names(order_motions(vec![requiring("a", &[]), requiring("b", &[]), requiring("c", &[])]).unwrap()), vec!["a", "b", "c"]);
+    assert_eq!(names(order_motions(vec![requiring("a", &["c"]), requiring("b", &[]), requiring("c", &[])]).unwrap()), vec!["b", "c", "a"]);
+    assert_eq!(names(order_motions(vec![requiring("a", &["b", "d"]), requiring("b", &["c"]), requiring("c", &[]), requiring("d", &[])]).unwrap()), vec!["c", "b", "d", "a"]);
+  }
+
+  #[test]
+  fn test_order_motions_missing() {
+    let error = order_motions(vec![requiring("a", &["x"])]).unwrap_err();
+    assert_eq!(format!("{}", error), "The 'a' motion requires the 'x' motion which could not be found.");
+  }
+
+  #[test]
+  fn test_order_motions_repeatable() {
+    let views = Motion { repeatable: true, ..requiring("views", &[]) };
+    let grants = Motion { repeatable: true, ..requiring("grants", &["views"]) };
+    assert_eq!(names(order_motions(vec![grants, views.clone()]).unwrap()), vec!["views", "grants"]);
+    let error = order_motions(vec![requiring("a", &["views"]), views]).unwrap_err();
+    assert_eq!(format!("{}", error), "The 'a' motion requires the 'views' motion which is repeatable, so it is executed after every motion which is not.");
+  }
+
+  #[test]
+  fn test_order_motions_cycle() {
+    let error = order_motions(vec![requiring("a", &[]), requiring("b", &["d"]), requiring("c", &["b"]), requiring("d", &["c"])]).unwrap_err();
+    assert_eq!(format!("{}", error), "Motions may not require each other in a cycle: b → d → c → b.");
+    let error = order_motions(vec![requiring("a", &["a"])]).unwrap_err();
+    assert_eq!(format!("{}", error), "Motions may not require each other in a cycle: a → a.");
+  }
+
+  #[test]
+  fn test_fixtures_requires() {
+    assert_eq!(names(find(Path::new("tests/fixtures/requires"), &FindOptions::default()).unwrap()), vec!["123456-foo", "456789-qux", "345678-baz", "567890-quux"]);
+  }
+
+  #[test]
+  fn test_fixtures_bad_requires() {
+    assert!(find(Path::new("tests/fixtures/bad/cycle"), &FindOptions::default()).is_err());
+    assert!(find(Path::new("tests/fixtures/bad/missing-requirement"), &FindOptions::default()).is_err());
   }
 
   #[test]
   fn test_fixtures_bad_templateless() {
-    assert!(find(Path::new("tests/fixtures/bad/templateless")).is_err());
+    assert!(find(Path::new("tests/fixtures/bad/templateless"), &FindOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_fixtures_namespaces() {
+    let names = |namespace: Option<&str>| find(Path::new("tests/fixtures/namespaces"), &FindOptions { namespace: namespace.map(String::from), ..FindOptions::default() }).unwrap().into_iter().map(|motion| motion.name).collect::<Vec<_>>();
+    assert_eq!(names(Some("app")), vec!["123456-users", "345678-posts"]);
+    assert_eq!(names(Some("billing")), vec!["234567-invoices"]);
+    assert!(names(None).is_empty());
+  }
+
+  #[test]
+  fn test_fixtures_tags() {
+    let root = Path::new("tests/fixtures/tags");
+    let motions = find(root, &FindOptions::default()).unwrap();
+    assert_eq!(motions.iter().map(|motion| motion.tags(root)).collect::<Vec<_>>(), vec![
+      vec![] as Vec<String>,
+      vec!["seed".to_string()],
+      vec!["dev-only".to_string()],
+      vec!["compliance".to_string()],
+    ]);
   }
 
   #[test]
   fn test_bad_names() {
-    assert!(find(Path::new("tests/fixtures/bad/names")).is_err());
+    assert!(find(Path::new("tests/fixtures/bad/names"), &FindOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_bad_duplicates() {
+    let error = find(Path::new("tests/fixtures/bad/duplicates"), &FindOptions::default()).unwrap_err();
+    assert_eq!(format!("{}", error), "Motion names must be unique but the '123456-foo' motion is in both 'tests/fixtures/bad/duplicates/a/123456-foo.add' and 'tests/fixtures/bad/duplicates/b/123456-foo.add'.");
+  }
+
+  #[test]
+  fn test_fixtures_irreversible() {
+    let motions = find(Path::new("tests/fixtures/irreversible"), &FindOptions::default()).unwrap();
+    assert_eq!(motions.iter().map(|motion| motion.is_irreversible()).collect::<Vec<_>>(), vec![false, true, true]);
+    assert_eq!(motions[1].sub_path, None);
+    assert!(motions[1].read_sub().is_err());
+    assert!(motions[2].meta.irreversible);
   }
 
   #[test]
@@ -357,7 +1713,8 @@ mod tests {
     assert_eq!(format!("{}", Motion {
       name: "foo".to_string(),
       add_path: pb("foo.add"),
-      sub_path: pb("foo.sub"),
+      sub_path: Some(pb("foo.sub")),
+      ..Motion::default()
     }), "foo");
   }
 
@@ -366,7 +1723,8 @@ mod tests {
     assert_eq!(format!("{}", Motion {
       name: "foo".to_string(),
       add_path: pb("hello/world/foo.add"),
-      sub_path: pb("hello/world/foo.sub"),
+      sub_path: Some(pb("hello/world/foo.sub")),
+      ..Motion::default()
     }), "hello/world/foo");
   }
 
@@ -375,7 +1733,8 @@ mod tests {
     assert_eq!(format!("{}", Motion {
       name: "foo".to_string(),
       add_path: pb("foo.add.txt"),
-      sub_path: pb("foo.sub.txt"),
+      sub_path: Some(pb("foo.sub.txt")),
+      ..Motion::default()
     }), "foo");
   }
 }
@@ -0,0 +1,72 @@
+use std::path::Path;
+use std::io::prelude::*;
+use std::fs;
+use std::fs::File;
+use colored::Colorize;
+use error::Error;
+
+/// Writes a schema dump to a file so that it may be committed alongside the
+/// motions which produced it.
+pub fn write(path: &Path, schema: &str) -> Result<(), Error> {
+  // Create the directory for our schema file.
+  if let Some(parent) = path.parent() { try!(fs::create_dir_all(parent)); }
+  // Write the schema to the file.
+  let mut file = try!(File::create(path));
+  try!(file.write_all(schema.as_bytes()));
+  println!("{} {}", "Dump".blue().bold(), path.display());
+  Ok(())
+}
+
+/// Checks that a schema file matches the schema we actually have, pointing out
+/// the first line which differs if it does not.
+pub fn check(path: &Path, schema: &str) -> Result<(), Error> {
+  // Read the schema we expect from our file.
+  let mut expected = String::new();
+  try!(try!(File::open(path).map_err(|error| error!("Schema file '{}' could not be opened: {}", path.display(), error))).read_to_string(&mut expected));
+  // If the schemas are the same, we are done!
+  if expected == schema { return Ok(()); }
+  // Otherwise find the first line where they differ.
+  let mut expected_lines = expected.lines();
+  let mut actual_lines = schema.lines();
+  let mut line = 0;
+  loop {
+    line += 1;
+    let (expected_line, actual_line) = (expected_lines.next(), actual_lines.next());
+    if expected_line != actual_line {
+      return Err(error!(
+        "The schema in '{}' does not match the database starting at line {}.\n  expected: {}\n  actual:   {}\nIf this change is intended, update the file with `accelerate schema dump {}`.",
+        path.display(),
+        line,
+        expected_line.unwrap_or("(end of file)"),
+        actual_line.unwrap_or("(end of file)"),
+        path.display()
+      ));
+    }
+    // If both have ended, the schemas only differ in their line endings.
+    if expected_line.is_none() {
+      return Err(error!("The schema in '{}' only differs from the database in its line endings.", path.display()));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use super::check;
+
+  #[test]
+  fn test_check_same() {
+    assert!(check(Path::new("tests/fixtures/schema/basic.txt"), "record 123456-foo\nrecord 234567-bar\n").is_ok());
+  }
+
+  #[test]
+  fn test_check_different() {
+    let error = check(Path::new("tests/fixtures/schema/basic.txt"), "record 123456-foo\n").unwrap_err();
+    assert_eq!(format!("{}", error), "The schema in 'tests/fixtures/schema/basic.txt' does not match the database starting at line 2.\n  expected: record 234567-bar\n  actual:   (end of file)\nIf this change is intended, update the file with `accelerate schema dump tests/fixtures/schema/basic.txt`.");
+  }
+
+  #[test]
+  fn test_check_missing() {
+    assert!(check(Path::new("tests/fixtures/schema/missing.txt"), "").is_err());
+  }
+}
@@ -0,0 +1,340 @@
+use std::error;
+use std::fmt;
+use std::io::prelude::*;
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use error::{Error, QueryError};
+
+/// Some text from a file which will be executed by a driver.
+#[derive(Eq, PartialEq, Debug)]
+pub struct Source {
+  pub path: PathBuf,
+  pub text: String,
+  /// The line in the file on which the text starts.
+  pub line: usize,
+  /// The files included by lines in our text, in the order they appear.
+  pub includes: Vec<Include>,
+}
+
+/// A line like `-- accelerate:include shared/audit.sql` which is replaced with
+/// the contents of another file.
+#[derive(Eq, PartialEq, Debug)]
+pub struct Include {
+  /// Where the include line starts in the text which includes it, as a 0-based
+  /// character offset.
+  pub start: usize,
+  /// The number of characters in the include line, not counting its line
+  /// ending.
+  pub length: usize,
+  pub source: Source,
+}
+
+impl Source {
+  /// Reads the entire contents of a file.
+  pub fn read(path: &Path) -> Result<Source, Error> {
+    let mut file = try!(File::open(path));
+    let mut text = String::new();
+    try!(file.read_to_string(&mut text));
+    Ok(Source {
+      path: path.to_path_buf(),
+      text: text,
+      line: 1,
+      includes: Vec::new(),
+    })
+  }
+
+  /// Reads the files included by our text, and the files they include, which
+  /// are found relative to the file that includes them.
+  pub fn read_includes(self) -> Result<Source, Error> {
+    self.read_includes_within(&mut Vec::new())
+  }
+
+  fn read_includes_within(mut self, stack: &mut Vec<(PathBuf, PathBuf)>) -> Result<Source, Error> {
+    let include_re = Regex::new(r"^\W*accelerate:include\s+(.*?)\s*$").unwrap();
+    // Remember that we are in this file so we can tell if it includes itself.
+    stack.push((try!(fs::canonicalize(&self.path)), self.path.clone()));
+    let mut start = 0;
+    for line in self.text.split('\n') {
+      let line = line.trim_right_matches('\r');
+      if let Some(captures) = include_re.captures(line) {
+        let path = self.path.parent().unwrap_or(Path::new("")).join(captures.at(1).unwrap());
+        let canonical_path = try!(fs::canonicalize(&path).map_err(|error| error!("Included file '{}' could not be read: {}", path.display(), error)));
+        if let Some(index) = stack.iter().position(|&(ref stack_path, _)| *stack_path == canonical_path) {
+          let mut cycle = stack[index..].iter().map(|&(_, ref path)| path.display().to_string()).collect::<Vec<String>>();
+          cycle.push(path.display().to_string());
+          return Err(error!("Motion files may not include each other in a cycle: {}.", cycle.join(" → ")));
+        }
+        self.includes.push(Include {
+          start: start,
+          length: line.chars().count(),
+          source: try!(try!(Source::read(&path)).read_includes_within(stack)),
+        });
+      }
+      start += line.chars().count() + 1;
+    }
+    stack.pop();
+    Ok(self)
+  }
+
+  /// Our text with every include line replaced by the text it includes. This
+  /// is the text which should be executed.
+  pub fn full_text(&self) -> String {
+    let mut text = String::new();
+    let mut chars = self.text.chars();
+    let mut position = 0;
+    for include in self.includes.iter() {
+      text.extend(chars.by_ref().take(include.start - position));
+      text.push_str(&include.source.full_text());
+      chars.by_ref().take(include.length).count();
+      position = include.start + include.length;
+    }
+    text.extend(chars);
+    text
+  }
+
+  /// Finds which source a 1-based character position in our full text came
+  /// from, and where in that source’s own text it is.
+  pub fn resolve(&self, position: usize) -> (&Source, usize) {
+    // How much further along the full text is than our own text. Included
+    // text may be shorter than the line which included it.
+    let position = position as isize;
+    let mut shift = 0;
+    for include in self.includes.iter() {
+      let start = include.start as isize + shift;
+      let full_length = include.source.full_text().chars().count() as isize;
+      if position <= start { break; }
+      if position <= start + full_length { return include.source.resolve((position - start) as usize); }
+      shift += full_length - include.length as isize;
+    }
+    (self, (position - shift) as usize)
+  }
+}
+
+/// A line and column in some text. Both start at 1.
+#[derive(Eq, PartialEq, Debug)]
+pub struct Location {
+  pub line: usize,
+  pub column: usize,
+}
+
+/// An error which happened while executing the contents of a motion file. If
+/// the driver told us where in the file things went wrong we will show the
+/// offending line with a caret under the offending token.
+#[derive(Debug)]
+pub struct SourceError {
+  path: PathBuf,
+  snippet: Option<Snippet>,
+  note: Option<String>,
+  error: Error,
+}
+
+#[derive(Debug)]
+struct Snippet {
+  location: Location,
+  line: String,
+  length: usize,
+}
+
+impl SourceError {
+  pub fn new(source: &Source, note: Option<String>, error: Error) -> Error {
+    // If the error is a query error, find the line and column in our text for
+    // the position the driver gave us.
+    let (source, snippet, error) = match error.downcast::<QueryError>() {
+      Ok(error) => {
+        let error = *error;
+        // The position may be in a file we included.
+        let (source, position) = source.resolve(error.position);
+        (source, snippet(source, position), error.error)
+      },
+      Err(error) => (source, None, error),
+    };
+    Box::new(SourceError {
+      path: source.path.clone(),
+      snippet: snippet,
+      note: note,
+      error: error,
+    })
+  }
+}
+
+impl fmt::Display for SourceError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(writeln!(f, "{}", self.error));
+    match self.snippet {
+      Some(ref snippet) => {
+        // Pad the gutter so that it is as wide as the line number.
+        let number = format!("{}", snippet.location.line);
+        let gutter = number.chars().map(|_| ' ').collect::<String>();
+        try!(writeln!(f, "{}--> {}:{}:{}", gutter, self.path.display(), snippet.location.line, snippet.location.column));
+        try!(writeln!(f, "{} |", gutter));
+        try!(writeln!(f, "{} | {}", number, snippet.line));
+        // Draw the caret under the token, making sure to keep any tabs so that
+        // it lines up.
+        let indent = snippet.line.chars().take(snippet.location.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>();
+        let caret = (0..snippet.length).map(|_| '^').collect::<String>();
+        try!(write!(f, "{} | {}{}", gutter, indent, caret));
+        if let Some(ref note) = self.note { try!(write!(f, "\n{} = note: {}", gutter, note)); }
+      },
+      None => {
+        try!(write!(f, " --> {}", self.path.display()));
+        if let Some(ref note) = self.note { try!(write!(f, "\n = note: {}", note)); }
+      },
+    }
+    Ok(())
+  }
+}
+
+impl error::Error for SourceError {
+  fn description(&self) -> &str {
+    self.error.description()
+  }
+}
+
+/// Gets the line and column of a 1-based character position in some text.
+pub fn locate(text: &str, position: usize) -> Option<Location> {
+  if position == 0 { return None; }
+  let mut location = Location { line: 1, column: 1 };
+  for (i, c) in text.chars().enumerate() {
+    // If we are at our position, we found our location.
+    if i + 1 == position { return Some(location); }
+    // Otherwise move our location forward by one character.
+    if c == '\n' {
+      location.line += 1;
+      location.column = 1;
+    } else {
+      location.column += 1;
+    }
+  }
+  None
+}
+
+fn snippet(source: &Source, position: usize) -> Option<Snippet> {
+  let mut location = match locate(&source.text, position) { Some(location) => location, None => return None };
+  let line = source.text.lines().nth(location.line - 1).unwrap_or("").trim_right_matches('\r').to_string();
+  // Our text may not start on the first line of the file.
+  location.line += source.line - 1;
+  // Underline the whole token which starts at our column. Quoted tokens go
+  // until the closing quote, placeholders until their closing brace and
+  // everything else goes until a character which can’t be part of an
+  // identifier.
+  let mut rest = line.chars().skip(location.column - 1);
+  let placeholder = rest.clone().take(2).collect::<String>() == "${";
+  let length = match rest.next() {
+    Some(quote) if quote == '"' || quote == '\'' => 1 + rest.position(|c| c == quote).map(|i| i + 1).unwrap_or(0),
+    Some(_) if placeholder => 1 + rest.position(|c| c == '}').map(|i| i + 1).unwrap_or(0),
+    Some(c) if c.is_alphanumeric() || c == '_' => 1 + rest.take_while(|&c| c.is_alphanumeric() || c == '_').count(),
+    _ => 1,
+  };
+  Some(Snippet {
+    location: location,
+    line: line,
+    length: length,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use error::{Error, QueryError};
+  use super::{locate, Location, Source, SourceError};
+
+  fn source(text: &str, line: usize) -> Source {
+    Source {
+      path: Path::new("foo.add.sql").to_path_buf(),
+      text: text.to_string(),
+      line: line,
+      includes: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn test_locate() {
+    assert_eq!(locate("abc\ndef", 1), Some(Location { line: 1, column: 1 }));
+    assert_eq!(locate("abc\ndef", 3), Some(Location { line: 1, column: 3 }));
+    assert_eq!(locate("abc\ndef", 5), Some(Location { line: 2, column: 1 }));
+    assert_eq!(locate("abc\ndef", 7), Some(Location { line: 2, column: 3 }));
+    assert_eq!(locate("abc\ndef", 8), None);
+    assert_eq!(locate("abc\ndef", 0), None);
+  }
+
+  #[test]
+  fn test_locate_multibyte() {
+    assert_eq!(locate("‘a’\nb", 5), Some(Location { line: 2, column: 1 }));
+  }
+
+  #[test]
+  fn test_source_error_position() {
+    let error = SourceError::new(
+      &source("begin;\n\ncrate table foo (bar text);\n", 1),
+      None,
+      Box::new(QueryError { position: 9, error: error!("syntax error") })
+    );
+    assert_eq!(format!("{}", error), "syntax error\n --> foo.add.sql:3:1\n  |\n3 | crate table foo (bar text);\n  | ^^^^^");
+  }
+
+  #[test]
+  fn test_source_error_quoted() {
+    let error = SourceError::new(
+      &source("select\t\"a b\" from foo;", 1),
+      None,
+      Box::new(QueryError { position: 8, error: error!("column does not exist") })
+    );
+    assert_eq!(format!("{}", error), "column does not exist\n --> foo.add.sql:1:8\n  |\n1 | select\t\"a b\" from foo;\n  |       \t^^^^^");
+  }
+
+  #[test]
+  fn test_source_error_offset_line() {
+    let error = SourceError::new(
+      &source("select 1;\nselect nope;\n", 9),
+      Some("Adds nope (NOPE-1)".to_string()),
+      Box::new(QueryError { position: 18, error: error!("column does not exist") })
+    );
+    assert_eq!(format!("{}", error), "column does not exist\n  --> foo.add.sql:10:8\n   |\n10 | select nope;\n   |        ^^^^\n   = note: Adds nope (NOPE-1)");
+  }
+
+  #[test]
+  fn test_source_error_placeholder() {
+    let error = SourceError::new(
+      &source("select ${column} from foo;", 1),
+      None,
+      Box::new(QueryError { position: 8, error: error!("Variable 'column' is not defined.") })
+    );
+    assert_eq!(format!("{}", error), "Variable 'column' is not defined.\n --> foo.add.sql:1:8\n  |\n1 | select ${column} from foo;\n  |        ^^^^^^^^^");
+  }
+
+  #[test]
+  fn test_read_includes() {
+    let source = Source::read(Path::new("tests/fixtures/include/234567-bar.add.sql")).unwrap().read_includes().unwrap();
+    assert_eq!(source.includes.len(), 1);
+    assert_eq!(source.includes[0].start, 21);
+    assert_eq!(source.includes[0].source.path, Path::new("tests/fixtures/include/shared/broken.sql"));
+    assert_eq!(source.full_text(), "create table bar ();\nselect 1;\nthis will fail;\n\nselect 1;\n");
+    // Before, inside and after the included text.
+    assert_eq!(source.resolve(8).1, 8);
+    assert_eq!(source.resolve(8).0.path, Path::new("tests/fixtures/include/234567-bar.add.sql"));
+    assert_eq!(source.resolve(42).1, 21);
+    assert_eq!(source.resolve(42).0.path, Path::new("tests/fixtures/include/shared/broken.sql"));
+    assert_eq!(source.resolve(49).1, 62);
+  }
+
+  #[test]
+  fn test_read_includes_cycle() {
+    let error = Source::read(Path::new("tests/fixtures/bad/include-cycle/123456-foo.add")).unwrap().read_includes().unwrap_err();
+    assert_eq!(format!("{}", error), "Motion files may not include each other in a cycle: tests/fixtures/bad/include-cycle/a.txt → tests/fixtures/bad/include-cycle/b.txt → tests/fixtures/bad/include-cycle/a.txt.");
+  }
+
+  #[test]
+  fn test_source_error_include() {
+    let source = Source::read(Path::new("tests/fixtures/include/234567-bar.add.sql")).unwrap().read_includes().unwrap();
+    let error = SourceError::new(&source, None, Box::new(QueryError { position: 42, error: error!("Query asked to fail.") }));
+    assert_eq!(format!("{}", error), "Query asked to fail.\n --> tests/fixtures/include/shared/broken.sql:2:11\n  |\n2 | this will fail;\n  |           ^^^^");
+  }
+
+  #[test]
+  fn test_source_error_no_position() {
+    let error = SourceError::new(&source("select 1;", 1), None, error!("connection lost"));
+    assert_eq!(format!("{}", error), "connection lost\n --> foo.add.sql");
+  }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::env;
+use regex::Regex;
+use error::{Error, QueryError};
+
+/// Values for the `${name}` placeholders in motion files. Placeholders like
+/// `${env:NAME}` are read from the environment instead, and `\${name}` is
+/// left alone as a literal `${name}`. Placeholders are only filled in for
+/// motions which ask for it, so motions written before are never changed.
+#[derive(Eq, PartialEq, Clone, Default, Debug)]
+pub struct Variables {
+  values: BTreeMap<String, String>,
+  /// Whether motions without a `variables` header have their placeholders
+  /// filled in.
+  enabled: bool,
+}
+
+/// Text with its placeholders replaced, which remembers where they were so
+/// positions in the new text can be traced back to the original.
+#[derive(Eq, PartialEq, Debug)]
+pub struct Substitution {
+  pub text: String,
+  replacements: Vec<Replacement>,
+}
+
+/// Where a placeholder was replaced. All of the numbers are 0-based character
+/// offsets and lengths.
+#[derive(Eq, PartialEq, Debug)]
+struct Replacement {
+  start: usize,
+  length: usize,
+  original_start: usize,
+  original_length: usize,
+}
+
+impl Variables {
+  /// Sets a variable, replacing any value it had before.
+  pub fn set(&mut self, name: &str, value: &str) {
+    self.values.insert(name.to_string(), value.to_string());
+  }
+
+  /// Fills in the placeholders of every motion which does not say otherwise.
+  pub fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+  }
+
+  /// Replaces the placeholders in the text of a motion if the motion, or
+  /// failing that our default, asks for it.
+  pub fn substitute_if(&self, text: &str, enabled: Option<bool>) -> Result<Substitution, Error> {
+    if enabled.unwrap_or(self.enabled) {
+      self.substitute(text)
+    } else {
+      Ok(Substitution { text: text.to_string(), replacements: Vec::new() })
+    }
+  }
+
+  /// Replaces every placeholder in some text. If a placeholder has no value
+  /// the error points to where it is. A `${` which ends a dollar quote tag,
+  /// like `$${"a": 1}$$`, starts a dollar quoted body and not a placeholder.
+  pub fn substitute(&self, text: &str) -> Result<Substitution, Error> {
+    let placeholder_re = Regex::new(r"(\\|\$[A-Za-z0-9_]*)?\$\{([A-Za-z_][A-Za-z0-9_]*|env:[^}]+)\}").unwrap();
+    let mut substitution = Substitution { text: String::new(), replacements: Vec::new() };
+    // We keep track of where we are in both texts in bytes, to slice with, and
+    // in characters, which is how positions are given.
+    let mut last_end = 0;
+    let mut original_chars = 0;
+    let mut chars = 0;
+    for captures in placeholder_re.captures_iter(text) {
+      let (start, end) = captures.pos(0).unwrap();
+      if captures.at(1).map_or(false, |prefix| prefix.starts_with("$")) { continue; }
+      // Copy everything between the last placeholder and this one.
+      let between = &text[last_end..start];
+      substitution.text.push_str(between);
+      original_chars += between.chars().count();
+      chars += between.chars().count();
+      // Find the value for our placeholder.
+      let name = captures.at(2).unwrap();
+      let value = if captures.at(1) == Some("\\") {
+        format!("${{{}}}", name)
+      } else if name.starts_with("env:") {
+        match env::var(&name[4..]) {
+          Ok(value) => value,
+          Err(_) => return Err(undefined(original_chars, error!("Environment variable '{}' is not defined.", &name[4..]))),
+        }
+      } else {
+        match self.values.get(name) {
+          Some(value) => value.clone(),
+          None => return Err(undefined(original_chars, error!("Variable '{}' is not defined. Define it with `--var {}=…` or in the config file.", name, name))),
+        }
+      };
+      let length = value.chars().count();
+      let original_length = text[start..end].chars().count();
+      substitution.text.push_str(&value);
+      substitution.replacements.push(Replacement {
+        start: chars,
+        length: length,
+        original_start: original_chars,
+        original_length: original_length,
+      });
+      original_chars += original_length;
+      chars += length;
+      last_end = end;
+    }
+    substitution.text.push_str(&text[last_end..]);
+    Ok(substitution)
+  }
+}
+
+fn undefined(original_start: usize, error: Error) -> Error {
+  Box::new(QueryError { position: original_start + 1, error: error })
+}
+
+impl Substitution {
+  /// Maps a 1-based character position in the substituted text to the same
+  /// place in the original text. Positions inside a replaced value map to the
+  /// start of its placeholder.
+  pub fn original_position(&self, position: usize) -> usize {
+    let mut original_position = position;
+    for replacement in self.replacements.iter() {
+      if position <= replacement.start { break; }
+      if position <= replacement.start + replacement.length { return replacement.original_start + 1; }
+      original_position = original_position + replacement.original_length - replacement.length;
+    }
+    original_position
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use error::QueryError;
+  use super::Variables;
+
+  fn variables() -> Variables {
+    let mut variables = Variables::default();
+    variables.set("schema", "app");
+    variables.set("role", "administrator");
+    variables
+  }
+
+  #[test]
+  fn test_substitute() {
+    assert_eq!(variables().substitute("grant all on schema ${schema} to ${role};").unwrap().text, "grant all on schema app to administrator;");
+    assert_eq!(variables().substitute("no placeholders").unwrap().text, "no placeholders");
+    assert_eq!(variables().substitute("\\${schema} ${schema}").unwrap().text, "${schema} app");
+  }
+
+  #[test]
+  fn test_substitute_dollar_quoted() {
+    assert_eq!(variables().substitute("select $${\"a\":1}$$::json;").unwrap().text, "select $${\"a\":1}$$::json;");
+    assert_eq!(variables().substitute("select $${schema}$$, '${ not a placeholder }';").unwrap().text, "select $${schema}$$, '${ not a placeholder }';");
+    assert_eq!(variables().substitute("select $body${a}$body$ from ${schema}.t;").unwrap().text, "select $body${a}$body$ from app.t;");
+  }
+
+  #[test]
+  fn test_substitute_if() {
+    let mut variables = variables();
+    assert_eq!(variables.substitute_if("${nope}", None).unwrap().text, "${nope}");
+    assert_eq!(variables.substitute_if("${schema}", Some(true)).unwrap().text, "app");
+    variables.set_enabled(true);
+    assert_eq!(variables.substitute_if("${schema}", None).unwrap().text, "app");
+    assert_eq!(variables.substitute_if("${nope}", Some(false)).unwrap().text, "${nope}");
+  }
+
+  #[test]
+  fn test_substitute_env() {
+    env::set_var("ACCELERATE_TEST_ROLE", "reader");
+    assert_eq!(variables().substitute("to ${env:ACCELERATE_TEST_ROLE};").unwrap().text, "to reader;");
+    assert!(variables().substitute("to ${env:ACCELERATE_TEST_NOPE};").is_err());
+  }
+
+  #[test]
+  fn test_substitute_undefined() {
+    let error = variables().substitute("select 1;\n‘${nope}’").unwrap_err();
+    let error = error.downcast::<QueryError>().unwrap();
+    assert_eq!(error.position, 12);
+    assert_eq!(format!("{}", error), "Variable 'nope' is not defined. Define it with `--var nope=…` or in the config file.");
+  }
+
+  #[test]
+  fn test_original_position() {
+    let substitution = variables().substitute("${role} ${schema} nope").unwrap();
+    assert_eq!(substitution.text, "administrator app nope");
+    // Inside of a replaced value.
+    assert_eq!(substitution.original_position(1), 1);
+    assert_eq!(substitution.original_position(13), 1);
+    assert_eq!(substitution.original_position(16), 9);
+    // After the replaced values.
+    assert_eq!(substitution.original_position(19), 19);
+    assert_eq!(substitution.original_position(14), 8);
+  }
+}
@@ -0,0 +1,2 @@
+create table foo ();
+-- accelerate:include shared/audit.sql
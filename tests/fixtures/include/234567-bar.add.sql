@@ -0,0 +1,3 @@
+create table bar ();
+-- accelerate:include shared/broken.sql
+select 1;
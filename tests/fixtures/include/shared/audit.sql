@@ -0,0 +1 @@
+create trigger audit;
@@ -0,0 +1,8 @@
+-- description: Adds foo
+-- author: Jane
+-- ticket: FOO-1
+-- tags: billing, seed
+-- transaction: on
+-- timeout: 30s
+
+foo+
@@ -0,0 +1,4 @@
+-- Description: Adds bar
+-- ticket: BAR-2
+bar+
+fail
@@ -0,0 +1 @@
+invoices-
@@ -0,0 +1,6 @@
+-- accelerate:add
+baz+
+
+-- accelerate:sub
+baz-
+fail
@@ -0,0 +1,4 @@
+-- accelerate:add
+foo+
+-- accelerate:sub
+foo-
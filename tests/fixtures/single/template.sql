@@ -0,0 +1,4 @@
+-- accelerate:add
+template+
+-- accelerate:sub
+template-
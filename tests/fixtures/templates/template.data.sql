@@ -0,0 +1,4 @@
+-- accelerate:add
+insert into {{name}} default values;
+-- accelerate:sub
+delete from {{name}};
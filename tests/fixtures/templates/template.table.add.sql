@@ -0,0 +1,2 @@
+-- author: {{author}}
+create table {{name}} (id serial primary key);
@@ -1,3 +1,7 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
 use support::{command, assert_output};
 
 #[test]
@@ -14,6 +18,38 @@ fn test_ls() {
   );
 }
 
+#[test]
+fn test_ls_max_depth() {
+  assert_output(
+    command().args(&["ls", "-d", "nested", "--max-depth", "2"]),
+    "nested/b/123456-foo\nnested/234567-bar\nnested/a/345678-baz\n",
+    ""
+  );
+  assert_output(
+    command().args(&["ls", "-d", "nested", "--max-depth", "deep"]),
+    "Error: Max depth 'deep' must be a whole number.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_ls_single() {
+  assert_output(
+    command().args(&["ls", "-d", "single"]),
+    "single/123456-foo\nsingle/234567-bar\n",
+    ""
+  );
+}
+
+#[test]
+fn test_ls_metadata() {
+  assert_output(
+    command().args(&["ls", "-d", "metadata"]),
+    "metadata/123456-foo  Adds foo (FOO-1) by Jane [billing, seed]\nmetadata/234567-bar  Adds bar (BAR-2)\n",
+    ""
+  );
+}
+
 #[test]
 fn test_status() {
   assert_output(
@@ -206,3 +242,772 @@ fn test_reset() {
     ""
   );
 }
+
+#[test]
+fn test_failure() {
+  assert_output(
+    command().args(&["up", "-d", "failure", "-t", "test", "-c", ""]),
+    "Add failure/123456-foo\nError: Query asked to fail.\n --> failure/234567-bar.add:3:12\n  |\n3 | this will  fail here\n  |            ^^^^\n",
+    ""
+  );
+}
+
+#[test]
+fn test_schema() {
+  assert_output(
+    command().args(&["schema", "dump", "-t", "test", "-c", "234567-bar,123456-foo"]),
+    "record 123456-foo\nrecord 234567-bar\n",
+    ""
+  );
+  assert_output(
+    command().args(&["schema", "check", "schema/basic.txt", "-d", "basic", "-t", "test", "-c", "123456-foo,234567-bar"]),
+    "",
+    ""
+  );
+  assert_output(
+    command().args(&["schema", "check", "schema/basic.txt", "-d", "basic", "-t", "test", "-c", "123456-foo"]),
+    "Error: There are 1 motions which have not been applied. Apply them with `accelerate up` before checking the schema.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_dump_schema() {
+  let path = env::temp_dir().join("accelerate-test-dump-schema.txt");
+  assert_output(
+    command().args(&["up", "-d", "basic", "-t", "test", "-c", "", "--dump-schema", path.to_str().unwrap()]),
+    &format!("Add basic/123456-foo\nAdd basic/234567-bar\nDump {}\n", path.display()),
+    ""
+  );
+  let mut schema = String::new();
+  File::open(&path).unwrap().read_to_string(&mut schema).unwrap();
+  assert_eq!(schema, "record 123456-foo\nrecord 234567-bar\n");
+}
+
+#[test]
+fn test_tenants() {
+  let conn = "customer_a:123456-foo;;customer_b,other";
+  assert_output(
+    command().args(&["up", "-d", "basic", "-t", "test", "-c", conn, "--tenants", "customer_%"]),
+    "Tenant customer_a\nAdd basic/234567-bar\nTenant customer_b\nAdd basic/123456-foo\nAdd basic/234567-bar\n",
+    ""
+  );
+  assert_output(
+    command().args(&["status", "-d", "basic", "-t", "test", "-c", conn, "--tenants", "customer_%"]),
+    "Tenant      Applied  Changed  Pending  Last\ncustomer_a        1        0        1  𝙭 basic/123456-foo\ncustomer_b        0        0        2  𝙭 \n",
+    ""
+  );
+  // A changed motion is reported for its tenant without stopping the others.
+  assert_output(
+    command().args(&["status", "-d", "basic", "-t", "test", "-c", "customer_a:123456-foo@changed,customer_a:234567-bar,customer_b:123456-foo", "--tenants", "customer_%"]),
+    "Tenant      Applied  Changed  Pending  Last\ncustomer_a        2        1        0  ! basic/234567-bar\ncustomer_b        1        0        1  𝙭 basic/123456-foo\nWarning: Motions have changed since they were applied to the 'customer_a' tenant. If the changes are intended, accept them with `accelerate accept-checksum`.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "basic", "-t", "test", "-c", conn, "--tenants", "customer_%", "--dump-schema", "schema.txt"]),
+    "Error: The schema can not be dumped when applying motions to tenants.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["status", "-d", "basic", "-t", "test", "-c", "", "--tenants-query", "select 'a'"]),
+    "Error: This driver does not support tenant queries.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_check() {
+  assert_output(
+    command().args(&["check", "-d", "basic", "-t", "test", "-c", ""]),
+    "Add basic/123456-foo\nAdd basic/234567-bar\nRollback all changes\n",
+    ""
+  );
+  assert_output(
+    command().args(&["check", "-d", "basic", "-t", "test", "-c", "123456-foo", "--sub"]),
+    "Add basic/234567-bar\nSub basic/234567-bar\nRollback all changes\n",
+    ""
+  );
+  assert_output(
+    command().args(&["check", "-d", "failure", "-t", "test", "-c", ""]),
+    "Add failure/123456-foo\nError: Query asked to fail.\n --> failure/234567-bar.add:3:12\n  |\n3 | this will  fail here\n  |            ^^^^\n",
+    ""
+  );
+}
+
+#[test]
+fn test_failure_section() {
+  assert_output(
+    command().args(&["down", "-d", "sections", "-t", "test", "-c", "345678-baz", "-y"]),
+    "Error: Query asked to fail.\n --> sections/345678-baz.sql:6:1\n  |\n6 | fail\n  | ^^^^\n",
+    ""
+  );
+}
+
+#[test]
+fn test_create_single() {
+  let dir = env::temp_dir().join("accelerate-test-create-single");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  fs::copy("tests/fixtures/basic/template.add", dir.join("template.add")).unwrap();
+  fs::copy("tests/fixtures/basic/template.sub", dir.join("template.sub")).unwrap();
+  assert_output(
+    command().args(&["create", "-d", dir.to_str().unwrap(), "--single", "foo"]),
+    "Error: Single file motions need a template with an extension, like `template.sql`, to tell them apart from other files.\n",
+    ""
+  );
+  fs::rename(dir.join("template.add"), dir.join("template.add.sql")).unwrap();
+  fs::rename(dir.join("template.sub"), dir.join("template.sub.sql")).unwrap();
+  let output = command().args(&["create", "-d", dir.to_str().unwrap(), "--single", "foo"]).output().unwrap();
+  let stdout = String::from_utf8(output.stdout).unwrap();
+  assert!(stdout.starts_with("Create "));
+  let path = stdout.trim_left_matches("Create ").trim_right();
+  let mut contents = String::new();
+  File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, "-- accelerate:add\ntemplate+\n-- accelerate:sub\ntemplate-\n");
+  assert_output(
+    command().args(&["add", "-d", dir.to_str().unwrap(), "-t", "test", "-c", ""]),
+    &format!("Add {}\n", path.trim_right_matches(".sql")),
+    ""
+  );
+}
+
+#[test]
+fn test_status_metadata() {
+  assert_output(
+    command().args(&["status", "-d", "metadata", "-t", "test", "-c", "123456-foo"]),
+    "✔ metadata/123456-foo  Adds foo (FOO-1) by Jane [billing, seed]\n𝙭 metadata/234567-bar  Adds bar (BAR-2)\n",
+    ""
+  );
+}
+
+#[test]
+fn test_failure_metadata() {
+  assert_output(
+    command().args(&["up", "-d", "metadata", "-t", "test", "-c", ""]),
+    "Add metadata/123456-foo\nError: Query asked to fail.\n --> metadata/234567-bar.add.sql:4:1\n  |\n4 | fail\n  | ^^^^\n  = note: Adds bar (BAR-2)\n",
+    ""
+  );
+}
+
+#[test]
+fn test_ls_requires() {
+  assert_output(
+    command().args(&["ls", "-d", "requires"]),
+    "requires/123456-foo\nrequires/b/456789-qux\nrequires/a/345678-baz\nrequires/567890-quux\n",
+    ""
+  );
+  assert_output(
+    command().args(&["ls", "-d", "bad/cycle"]),
+    "Error: Motions may not require each other in a cycle: 123456-foo → 234567-bar → 123456-foo.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["ls", "-d", "bad/missing-requirement"]),
+    "Error: The 'bad/missing-requirement/123456-foo' motion requires the '000000-nope' motion which could not be found.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_ls_duplicates() {
+  assert_output(
+    command().args(&["ls", "-d", "bad/duplicates"]),
+    "Error: Motion names must be unique but the '123456-foo' motion is in both 'bad/duplicates/a/123456-foo.add' and 'bad/duplicates/b/123456-foo.add'.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_irreversible() {
+  assert_output(
+    command().args(&["status", "-d", "irreversible", "-t", "test", "-c", "123456-foo,234567-bar"]),
+    "✔ irreversible/123456-foo\n✔ irreversible/234567-bar (irreversible)\n𝙭 irreversible/345678-baz (irreversible)\n",
+    ""
+  );
+  assert_output(
+    command().args(&["down", "-d", "irreversible", "-t", "test", "-c", "123456-foo,234567-bar", "-y"]),
+    "Error: The 'irreversible/234567-bar' motion is irreversible so it can not be subbed. No motions were subbed.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["redo", "-d", "irreversible", "-t", "test", "-c", "123456-foo,234567-bar,345678-baz", "-y"]),
+    "Error: The 'irreversible/345678-baz' motion is irreversible so it can not be subbed. No motions were subbed.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["down", "-d", "irreversible", "-t", "test", "-c", "123456-foo", "-y"]),
+    "Sub irreversible/123456-foo\n",
+    ""
+  );
+  assert_output(
+    command().args(&["check", "-d", "irreversible", "-t", "test", "-c", "123456-foo", "--sub"]),
+    "Add irreversible/234567-bar\nAdd irreversible/345678-baz\nStop irreversible/345678-baz is irreversible\nRollback all changes\n",
+    ""
+  );
+}
+
+#[test]
+fn test_repeatable() {
+  assert_output(
+    command().args(&["ls", "-d", "repeatable"]),
+    "repeatable/123456-foo\nrepeatable/234567-bar\nrepeatable/views (repeatable)\nrepeatable/grants (repeatable)\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "repeatable", "-t", "test", "-c", "123456-foo"]),
+    "Add repeatable/234567-bar\nRepeat repeatable/views\nRepeat repeatable/grants\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "repeatable", "-t", "test", "-c", "123456-foo,234567-bar;views@d350e8ac8fce394c,grants@0000000000000000"]),
+    "Repeat repeatable/grants\n",
+    ""
+  );
+  assert_output(
+    command().args(&["status", "-d", "repeatable", "-t", "test", "-c", "123456-foo,234567-bar;views@d350e8ac8fce394c,grants@0000000000000000"]),
+    "✔ repeatable/123456-foo\n✔ repeatable/234567-bar\n✔ repeatable/views (repeatable)\n𝙭 repeatable/grants (repeatable)\n",
+    ""
+  );
+  assert_output(
+    command().args(&["reset", "-d", "repeatable", "-t", "test", "-c", "123456-foo,234567-bar;views@d350e8ac8fce394c,grants@f52dc003bd9ff121", "-y"]),
+    "Sub repeatable/234567-bar\nSub repeatable/123456-foo\nAdd repeatable/123456-foo\nAdd repeatable/234567-bar\nRepeat repeatable/views\nRepeat repeatable/grants\n",
+    ""
+  );
+}
+
+#[test]
+fn test_out_of_order() {
+  assert_output(
+    command().args(&["status", "-d", "basic", "-t", "test", "-c", "234567-bar"]),
+    "↩ basic/123456-foo\n✔ basic/234567-bar\nWarning: The 'basic/123456-foo' motion has not been applied but comes before motions which have been. If it arrived in a merge, apply it with `accelerate up --allow-out-of-order`.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "basic", "-t", "test", "-c", "234567-bar"]),
+    "Error: The 'basic/123456-foo' motion has not been applied but comes before motions which have been. If it arrived in a merge, apply it with `accelerate up --allow-out-of-order`.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "basic", "-t", "test", "-c", "234567-bar", "--allow-out-of-order"]),
+    "Add basic/123456-foo\n",
+    ""
+  );
+  assert_output(
+    command().args(&["down", "-d", "basic", "-t", "test", "-c", "234567-bar", "-y"]),
+    "Sub basic/234567-bar\n",
+    ""
+  );
+  assert_output(
+    command().args(&["redo", "-d", "basic", "-t", "test", "-c", "234567-bar", "-y"]),
+    "Error: The 'basic/123456-foo' motion has not been applied but comes before motions which have been. If it arrived in a merge, apply it with `accelerate up --allow-out-of-order`.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["redo", "-d", "basic", "-t", "test", "-c", "234567-bar", "-y", "--allow-out-of-order"]),
+    "Sub basic/234567-bar\nAdd basic/123456-foo\nAdd basic/234567-bar\n",
+    ""
+  );
+}
+
+#[test]
+fn test_checksum() {
+  assert_output(
+    command().args(&["up", "-d", "basic", "-t", "test", "-c", "123456-foo@d3f21ee6d3548e9d"]),
+    "Add basic/234567-bar\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "basic", "-t", "test", "-c", "123456-foo@0000000000000000"]),
+    "Error: The 'basic/123456-foo' motion has changed since it was applied. If the change is intended, accept it with `accelerate accept-checksum 123456-foo`.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["status", "-d", "basic", "-t", "test", "-c", "123456-foo@0000000000000000,234567-bar@69a57dad85d01233"]),
+    "! basic/123456-foo\n✔ basic/234567-bar\nWarning: The 'basic/123456-foo' motion has changed since it was applied. If the change is intended, accept it with `accelerate accept-checksum 123456-foo`.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["accept-checksum", "-d", "basic", "-t", "test", "-c", "123456-foo@0000000000000000", "123456-foo"]),
+    "Accept basic/123456-foo\n",
+    ""
+  );
+  assert_output(
+    command().args(&["accept-checksum", "-d", "basic", "-t", "test", "-c", "123456-foo", "basic/234567-bar"]),
+    "Error: The 'basic/234567-bar' motion has not been applied so there is no checksum to accept.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_variables() {
+  assert_output(
+    command().args(&["up", "-d", "variables", "-t", "test", "-c", ""]),
+    "Add variables/123456-foo\nError: Variable 'column' is not defined. Define it with `--var column=…` or in the config file.\n --> variables/234567-bar.add:1:29\n  |\n1 | create table ${schema}.bar (${column} text);\n  |                             ^^^^^^^^^\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "variables", "-t", "test", "-c", "", "--var", "column=id"]),
+    "Add variables/123456-foo\nAdd variables/234567-bar\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "variables", "-t", "test", "-c", "", "--var", "role=fail"]),
+    "Error: Query asked to fail.\n --> variables/123456-foo.add:1:36\n  |\n1 | grant usage on schema ${schema} to ${role};\n  |                                    ^^^^^^^\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "variables", "-t", "test", "-c", "", "--var", "column"]),
+    "Error: Variable 'column' must look like `name=value`.\n",
+    ""
+  );
+  // Motions which don’t turn variables on are executed as they are.
+  let dir = env::temp_dir().join("accelerate-test-variables");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  File::create(dir.join("template.add")).unwrap();
+  File::create(dir.join("template.sub")).unwrap();
+  File::create(dir.join("123456-foo.add")).unwrap().write_all(b"select '${nope}', $${\"a\": 1}$$::json;\n").unwrap();
+  File::create(dir.join("234567-bar.add")).unwrap().write_all(b"-- variables: on\nselect '${nope}';\n").unwrap();
+  let d = dir.to_str().unwrap();
+  assert_output(
+    command().args(&["up", "-d", d, "-t", "test", "-c", ""]),
+    &format!("Add {0}/123456-foo\nError: Variable 'nope' is not defined. Define it with `--var nope=…` or in the config file.\n --> {0}/234567-bar.add:2:9\n  |\n2 | select '${{nope}}';\n  |         ^^^^^^^\n", d),
+    ""
+  );
+}
+
+#[test]
+fn test_ls_mixed() {
+  assert_output(
+    command().args(&["ls", "-d", "mixed"]),
+    "mixed/123456-foo\nmixed/billing/234567-invoices\nmixed/search/345678-index\n",
+    ""
+  );
+}
+
+#[test]
+fn test_create_nearest_template() {
+  let dir = env::temp_dir().join("accelerate-test-create-nearest-template");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(dir.join("search")).unwrap();
+  fs::copy("tests/fixtures/mixed/template.add.sql", dir.join("template.add.sql")).unwrap();
+  fs::copy("tests/fixtures/mixed/template.sub.sql", dir.join("template.sub.sql")).unwrap();
+  fs::copy("tests/fixtures/mixed/search/template.add.json", dir.join("search/template.add.json")).unwrap();
+  fs::copy("tests/fixtures/mixed/search/template.sub.json", dir.join("search/template.sub.json")).unwrap();
+  for &(name, extension, contents) in &[("billing/invoices", ".add.sql", "template+\n"), ("search/index", ".add.json", "{ \"template\": \"+\" }\n")] {
+    let output = command().args(&["create", "-d", dir.to_str().unwrap(), name]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let path = stdout.lines().next().unwrap().trim_left_matches("Create ");
+    assert!(path.ends_with(extension));
+    let mut text = String::new();
+    File::open(path).unwrap().read_to_string(&mut text).unwrap();
+    assert_eq!(text, contents);
+  }
+}
+
+#[test]
+fn test_create_named_template() {
+  let dir = env::temp_dir().join("accelerate-test-create-named-template");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  for file_name in &["template.add.sql", "template.sub.sql", "template.table.add.sql", "template.table.sub.sql"] {
+    fs::copy(format!("tests/fixtures/templates/{}", file_name), dir.join(file_name)).unwrap();
+  }
+  let output = command().env("ACCELERATE_AUTHOR", "Jane").args(&["create", "-d", dir.to_str().unwrap(), "--template", "table", "invoices"]).output().unwrap();
+  let stdout = String::from_utf8(output.stdout).unwrap();
+  let paths = stdout.lines().map(|line| line.trim_left_matches("Create ")).collect::<Vec<_>>();
+  let mut add = String::new();
+  File::open(paths[0]).unwrap().read_to_string(&mut add).unwrap();
+  assert_eq!(add, "-- author: Jane\ncreate table invoices (id serial primary key);\n");
+  let mut sub = String::new();
+  File::open(paths[1]).unwrap().read_to_string(&mut sub).unwrap();
+  assert_eq!(sub, "drop table invoices;\n");
+  assert_output(
+    command().args(&["create", "-d", dir.to_str().unwrap(), "--template", "nope", "invoices"]),
+    &format!("Error: Template 'nope' was not found for directory '{}'.\n", dir.display()),
+    ""
+  );
+}
+
+#[test]
+fn test_include() {
+  assert_output(
+    command().args(&["up", "-d", "include", "-t", "test", "-c", ""]),
+    "Add include/123456-foo\nError: Query asked to fail.\n --> include/shared/broken.sql:2:11\n  |\n2 | this will fail;\n  |           ^^^^\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "bad/include-cycle", "-t", "test", "-c", ""]),
+    "Error: Motion files may not include each other in a cycle: bad/include-cycle/a.txt → bad/include-cycle/b.txt → bad/include-cycle/a.txt.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_ls_ignore() {
+  assert_output(
+    command().args(&["ls", "-d", "ignore"]),
+    "ignore/123456-foo\n",
+    ""
+  );
+}
+
+#[test]
+fn test_rename() {
+  let dir = env::temp_dir().join("accelerate-test-rename");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  for file_name in &["template.add", "template.sub", "123456-foo.add", "123456-foo.sub", "234567-bar.add", "234567-bar.sub"] {
+    fs::copy(format!("tests/fixtures/basic/{}", file_name), dir.join(file_name)).unwrap();
+  }
+  let d = dir.to_str().unwrap();
+  assert_output(
+    command().args(&["rename", "-d", d, "-t", "test", "-c", "123456-foo", "123456-foo", "234567-bar"]),
+    "Error: There is already a motion named '234567-bar'.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["rename", "-d", d, "-t", "test", "-c", "123456-foo", "123456-foo", "a/b"]),
+    "Error: The name 'a/b' can not be used for a motion, names may not be empty or have a directory.\n",
+    ""
+  );
+  // Nothing is remembered about a rename which failed.
+  assert!(!dir.join("accelerate.conf").exists());
+  assert_output(
+    command().args(&["rename", "-d", d, "-t", "test", "-c", "123456-foo", "123456-foo", "123456-baz"]),
+    &format!("Rename {0}/123456-foo to {0}/123456-baz\nRecord {0}/123456-baz\n", d),
+    ""
+  );
+  assert!(dir.join("123456-baz.add").exists());
+  assert!(dir.join("123456-baz.sub").exists());
+  assert!(!dir.join("123456-foo.add").exists());
+  // Other databases catch up with the rename the next time they change, but
+  // checking them changes nothing.
+  assert_output(
+    command().args(&["check", "-d", d, "-t", "test", "-c", "123456-foo"]),
+    &format!("Add {0}/234567-bar\nRollback all changes\n", d),
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", d, "-t", "test", "-c", "123456-foo"]),
+    &format!("Rename 123456-foo to {0}/123456-baz\nAdd {0}/234567-bar\n", d),
+    ""
+  );
+  assert_output(
+    command().args(&["status", "-d", d, "-t", "test", "-c", "123456-foo"]),
+    &format!("✔ {0}/123456-baz\n𝙭 {0}/234567-bar\n", d),
+    ""
+  );
+}
+
+#[test]
+fn test_tags() {
+  assert_output(
+    command().args(&["ls", "-d", "tags", "--tag", "seed", "--tag", "compliance"]),
+    "tags/seed/234567-fake-users\ntags/456789-audit  [compliance]\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "tags", "-t", "test", "-c", "", "--exclude-tag", "seed", "--exclude-tag", "dev-only"]),
+    "Add tags/123456-users\nAdd tags/456789-audit\n",
+    ""
+  );
+  assert_output(
+    command().args(&["status", "-d", "tags", "-t", "test", "-c", "123456-users", "--exclude-tag", "seed"]),
+    "✔ tags/123456-users\n𝙭 tags/345678-debug.dev-only\n𝙭 tags/456789-audit  [compliance]\n",
+    ""
+  );
+}
+
+#[test]
+fn test_tags_between_applied() {
+  // The seed and debug motions sit between two applied motions, but as we
+  // left them out they aren’t late.
+  let conn = "123456-users,456789-audit";
+  let exclude = ["--exclude-tag", "seed", "--exclude-tag", "dev-only"];
+  assert_output(
+    command().args(&["status", "-d", "tags", "-t", "test", "-c", conn]).args(&exclude),
+    "✔ tags/123456-users\n✔ tags/456789-audit  [compliance]\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "tags", "-t", "test", "-c", conn]).args(&exclude),
+    "",
+    ""
+  );
+  assert_output(
+    command().args(&["redo", "-d", "tags", "-t", "test", "-c", conn, "-y"]).args(&exclude),
+    "Sub tags/456789-audit\nAdd tags/456789-audit\n",
+    ""
+  );
+  assert_output(
+    command().args(&["down", "-d", "tags", "-t", "test", "-c", conn, "-y"]),
+    "Sub tags/456789-audit\nSub tags/123456-users\n",
+    ""
+  );
+  // Leaving out an applied motion leaves out its record too.
+  assert_output(
+    command().args(&["down", "-d", "tags", "-t", "test", "-c", conn, "-y", "--exclude-tag", "compliance"]),
+    "Sub tags/123456-users\n",
+    ""
+  );
+}
+
+#[test]
+fn test_namespaces() {
+  assert_output(
+    command().args(&["ls", "-d", "namespaces"]),
+    "namespaces/123456-users\nnamespaces/345678-posts\n",
+    ""
+  );
+  assert_output(
+    command().args(&["ls", "-d", "namespaces/billing"]),
+    "namespaces/billing/234567-invoices\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "namespaces", "-t", "test", "-c", "app/123456-users,billing/234567-invoices"]),
+    "Add namespaces/345678-posts\n",
+    ""
+  );
+  assert_output(
+    command().args(&["status", "-d", "namespaces/billing", "-t", "test", "-c", "app/123456-users,billing/234567-invoices"]),
+    "✔ namespaces/billing/234567-invoices\n",
+    ""
+  );
+  // The motions of a directory in another namespace are never ours.
+  assert_output(
+    command().args(&["ls", "-d", "namespaces/billing", "--namespace", "app"]),
+    "",
+    ""
+  );
+  // Records from before the motions had a namespace must be adopted first, or
+  // the motions would be applied again.
+  assert_output(
+    command().args(&["up", "-d", "namespaces", "-t", "test", "-c", "123456-users,234567-invoices"]),
+    "Error: The 'namespaces/123456-users' motion was recorded before motions had a namespace, so it would be applied again. Move the records of these motions into the namespace first with `accelerate adopt-records`.\n",
+    ""
+  );
+  assert_output(
+    command().args(&["adopt-records", "-d", "namespaces", "-t", "test", "-c", "123456-users,234567-invoices"]),
+    "Adopt namespaces/123456-users\n",
+    ""
+  );
+  assert_output(
+    command().args(&["adopt-records", "-d", "namespaces", "-t", "test", "-c", "app/123456-users,123456-users"]),
+    "",
+    ""
+  );
+}
+
+#[test]
+fn test_create_sequential() {
+  let dir = env::temp_dir().join("accelerate-test-create-sequential");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  fs::copy("tests/fixtures/basic/template.add", dir.join("template.add")).unwrap();
+  fs::copy("tests/fixtures/basic/template.sub", dir.join("template.sub")).unwrap();
+  File::create(dir.join("accelerate.conf")).unwrap().write_all(b"naming = sequential\n").unwrap();
+  let d = dir.to_str().unwrap();
+  assert_output(
+    command().args(&["create", "-d", d, "foo"]),
+    &format!("Create {0}/0001-foo.add\nCreate {0}/0001-foo.sub\n", d),
+    ""
+  );
+  assert_output(
+    command().args(&["create", "-d", d, "bar"]),
+    &format!("Create {0}/0002-bar.add\nCreate {0}/0002-bar.sub\n", d),
+    ""
+  );
+  assert_output(
+    command().args(&["create", "-d", d, "0002-baz"]),
+    "Error: The motion number 0002 is not higher than 2, the highest motion number so far.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_ls_order() {
+  assert_output(
+    command().args(&["ls", "-d", "natural"]),
+    "natural/9-init\nnatural/10-users\n",
+    ""
+  );
+  assert_output(
+    command().args(&["ls", "-d", "natural", "--order", "lexical"]),
+    "natural/10-users\nnatural/9-init\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "natural", "-t", "test", "-c", "9-init"]),
+    "Add natural/10-users\n",
+    ""
+  );
+}
+
+#[test]
+fn test_squash() {
+  let dir = env::temp_dir().join("accelerate-test-squash");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  for file_name in &["template.add", "template.sub", "123456-foo.add", "123456-foo.sub", "234567-bar.add", "234567-bar.sub"] {
+    fs::copy(format!("tests/fixtures/basic/{}", file_name), dir.join(file_name)).unwrap();
+  }
+  fs::copy("tests/fixtures/squash/345678-baz.add", dir.join("345678-baz.add")).unwrap();
+  let d = dir.to_str().unwrap();
+  assert_output(
+    command().args(&["squash", "-d", d, "-t", "test", "-c", "123456-foo", "--until", "234567-bar"]),
+    &format!("Error: Only 1 of the 2 motions up to the '{}/234567-bar' motion have been applied. Apply them all before squashing.\n", d),
+    ""
+  );
+  assert_output(
+    command().args(&["squash", "-d", d, "-t", "test", "-c", "123456-foo,234567-bar", "--until", "234567-bar"]),
+    &format!(
+      "Create {0}/234567-baseline.add\nCreate {0}/234567-baseline.sub\nArchive {0}/123456-foo\nArchive {0}/234567-bar\nSquash 123456-foo, 234567-bar into {0}/234567-baseline\n",
+      d
+    ),
+    ""
+  );
+  let mut text = String::new();
+  File::open(dir.join("234567-baseline.sub")).unwrap().read_to_string(&mut text).unwrap();
+  assert_eq!(text, "bar-\nfoo-\n");
+  assert!(dir.join("archive/123456-foo.add").exists());
+  assert_output(
+    command().args(&["ls", "-d", d]),
+    &format!("{0}/234567-baseline\n{0}/345678-baz (irreversible)\n", d),
+    ""
+  );
+}
+
+#[test]
+fn test_squash_headers() {
+  let dir = env::temp_dir().join("accelerate-test-squash-headers");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(dir.join("archive")).unwrap();
+  for file_name in &["template.add", "template.sub"] {
+    fs::copy(format!("tests/fixtures/basic/{}", file_name), dir.join(file_name)).unwrap();
+  }
+  File::create(dir.join("123456-foo.add")).unwrap().write_all(b"-- description: Foo\n-- transaction: off\nfoo+\n").unwrap();
+  File::create(dir.join("123456-foo.sub")).unwrap().write_all(b"foo-\n").unwrap();
+  File::create(dir.join("234567-bar.add")).unwrap().write_all(b"-- tags: seed\n-- transaction: off\nbar+\n").unwrap();
+  File::create(dir.join("234567-bar.sub")).unwrap().write_all(b"bar-\n").unwrap();
+  File::create(dir.join("345678-baz.add")).unwrap().write_all(b"baz+\n").unwrap();
+  File::create(dir.join("345678-baz.sub")).unwrap().write_all(b"baz-\n").unwrap();
+  File::create(dir.join(".accelerateignore")).unwrap().write_all(b"/archive/\n").unwrap();
+  File::create(dir.join("archive/234567-bar.sub")).unwrap();
+  let d = dir.to_str().unwrap();
+  assert_output(
+    command().args(&["squash", "-d", d, "-t", "test", "-c", "", "--until", "345678-baz"]),
+    &format!("Error: The '{0}/345678-baz' motion has a different `transaction`, `timeout` or `variables` header than the '{0}/123456-foo' motion, so they can not be squashed together.\n", d),
+    ""
+  );
+  assert_output(
+    command().args(&["squash", "-d", d, "-t", "test", "-c", "", "--until", "234567-bar"]),
+    &format!("Error: The archived file '{}/archive/234567-bar.sub' already exists.\n", d),
+    ""
+  );
+  assert!(dir.join("123456-foo.add").exists());
+  assert!(!dir.join("234567-baseline.add").exists());
+  fs::remove_file(dir.join("archive/234567-bar.sub")).unwrap();
+  assert_output(
+    command().args(&["squash", "-d", d, "-t", "test", "-c", "", "--until", "234567-bar"]),
+    &format!(
+      "Create {0}/234567-baseline.add\nCreate {0}/234567-baseline.sub\nArchive {0}/123456-foo\nArchive {0}/234567-bar\n",
+      d
+    ),
+    ""
+  );
+  let mut text = String::new();
+  File::open(dir.join("234567-baseline.add")).unwrap().read_to_string(&mut text).unwrap();
+  assert_eq!(text, "-- transaction: off\nfoo+\nbar+\n");
+}
+
+#[test]
+fn test_squash_catch_up() {
+  assert_output(
+    command().args(&["up", "-d", "squash", "-t", "test", "-c", "100000-old,234567-bar"]),
+    "Squash 123456-foo, 234567-bar into squash/234567-baseline\nAdd squash/345678-baz\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "squash", "-t", "test", "-c", ""]),
+    "Add squash/234567-baseline\nAdd squash/345678-baz\n",
+    ""
+  );
+  assert_output(
+    command().args(&["up", "-d", "squash", "-t", "test", "-c", "234567-bar"]),
+    "Error: Only some of the motions squashed into the 'squash/234567-baseline' motion were applied, these were not: 123456-foo. Apply them by hand before the squashed motion can be recorded.\n",
+    ""
+  );
+}
+
+#[test]
+fn test_gen_sub() {
+  let dir = env::temp_dir().join("accelerate-test-gen-sub");
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  fs::copy("tests/fixtures/templates/template.add.sql", dir.join("template.add.sql")).unwrap();
+  fs::copy("tests/fixtures/templates/template.sub.sql", dir.join("template.sub.sql")).unwrap();
+  File::create(dir.join("accelerate.conf")).unwrap().write_all(b"naming = sequential\n").unwrap();
+  File::create(dir.join("0001-users.add.sql")).unwrap().write_all(b"create table users (id serial);\nalter table users add column name text;\n").unwrap();
+  let d = dir.to_str().unwrap();
+  let sub = "alter table users drop column name;\ndrop table users;\n";
+  assert_output(
+    command().args(&["gen-sub", "-d", d, "0001-users"]),
+    &format!("Create {}/0001-users.sub.sql\n", d),
+    ""
+  );
+  let mut contents = String::new();
+  File::open(dir.join("0001-users.sub.sql")).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, sub);
+  assert_output(
+    command().args(&["gen-sub", "-d", d, "0001-users"]),
+    &format!("Error: The '{}/0001-users' motion can already be subbed.\n", d),
+    ""
+  );
+  assert_output(
+    command().args(&["create", "-d", d, "--single", "--from", dir.join("0001-users.add.sql").to_str().unwrap(), "copy"]),
+    &format!("Create {}/0002-copy.sql\n", d),
+    ""
+  );
+  let mut contents = String::new();
+  File::open(dir.join("0002-copy.sql")).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, format!("-- accelerate:add\ncreate table users (id serial);\nalter table users add column name text;\n-- accelerate:sub\n{}", sub));
+  File::create(dir.join("posts.txt")).unwrap().write_all(b"create table posts (id serial);\n").unwrap();
+  File::create(dir.join("0003-posts.add.sql")).unwrap().write_all(b"-- accelerate:include posts.txt\n").unwrap();
+  assert_output(
+    command().args(&["gen-sub", "-d", d, "-t", "test", "-c", "0001-users,0003-posts", "0003-posts"]),
+    &format!("Create {0}/0003-posts.sub.sql\nAccept {0}/0003-posts\n", d),
+    ""
+  );
+  let mut contents = String::new();
+  File::open(dir.join("0003-posts.sub.sql")).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, "drop table posts;\n");
+  // A copy in another directory still has the text its original included.
+  assert_output(
+    command().args(&["create", "-d", d, "--from", dir.join("0003-posts.add.sql").to_str().unwrap(), "copies/posts-copy"]),
+    &format!("Create {0}/copies/0004-posts-copy.add.sql\nCreate {0}/copies/0004-posts-copy.sub.sql\n", d),
+    ""
+  );
+  let mut contents = String::new();
+  File::open(dir.join("copies/0004-posts-copy.add.sql")).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, "create table posts (id serial);\n\n");
+  let mut contents = String::new();
+  File::open(dir.join("copies/0004-posts-copy.sub.sql")).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, "drop table posts;\n");
+  assert_output(
+    command().args(&["up", "-d", d, "-t", "test", "-c", "0001-users,0002-copy,0003-posts"]),
+    &format!("Add {}/copies/0004-posts-copy\n", d),
+    ""
+  );
+  File::create(dir.join("0005-tags.sql")).unwrap().write_all(b"-- accelerate:add\ncreate table tags (id serial);\n").unwrap();
+  assert_output(
+    command().args(&["gen-sub", "-d", d, "0005-tags"]),
+    &format!("Create {}/0005-tags.sql\n", d),
+    ""
+  );
+  let mut contents = String::new();
+  File::open(dir.join("0005-tags.sql")).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, "-- accelerate:add\ncreate table tags (id serial);\n-- accelerate:sub\ndrop table tags;\n");
+  assert!(!dir.join(".0005-tags.sql.tmp").exists());
+}